@@ -0,0 +1,67 @@
+use csv;
+use model::{PreConnections, Stops};
+use pathing::{best_from_tree, search_all};
+use projection::Projection;
+use spatial::StopIndex;
+use std::error::Error;
+use std::path::Path;
+use time::CostModel;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+struct RawDestination {
+    name: String,
+    lat: f64,
+    lon: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Destination {
+    pub name: String,
+    // Distances in km
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Reads a CSV of places people want to get to, e.g. grocery stores.
+pub fn read_destinations(
+    path: &Path,
+    projection: &Projection,
+) -> Result<Vec<Destination>, Box<Error>> {
+    let mut out = Vec::new();
+    for row in csv::Reader::from_path(path)?.into_deserialize() {
+        let raw: RawDestination = row?;
+        let (x, y) = projection.to_x_y(raw.lat, raw.lon);
+        out.push(Destination {
+            name: raw.name,
+            x: x,
+            y: y,
+        });
+    }
+    Ok(out)
+}
+
+/// How many of the destinations can be reached from `start` within `limit`
+/// seconds. Searches once from `start` and shares the result between every
+/// destination. The search goes by plain travel time, since that's what
+/// `limit` is in, so only the transfer limit and wait strategy of
+/// `cost_model` are used.
+pub fn reachable(
+    stops: &Stops,
+    index: &StopIndex,
+    connections: &PreConnections,
+    cost_model: &CostModel,
+    start: (f64, f64),
+    destinations: &[Destination],
+    limit: f32,
+) -> usize {
+    let plain = CostModel {
+        max_transfers: cost_model.max_transfers,
+        wait: cost_model.wait,
+        ..CostModel::default()
+    };
+    let tree = search_all(stops, index, connections, &plain, start, limit);
+    destinations
+        .iter()
+        .filter(|d| best_from_tree(index, &plain, &tree, start, (d.x, d.y)).total() <= limit)
+        .count()
+}
@@ -0,0 +1,274 @@
+use bincode::{self, deserialize, serialize, serialize_into, serialized_size};
+use fnv::FnvHasher;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::to_string;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Cache files are laid out as:
+//
+//   MAGIC
+//   CACHE_VERSION: u32
+//   meta: M, describing what the data was built from
+//   checksum: u64, the FNV hash of the data
+//   data: T
+//
+// with everything after MAGIC encoded with bincode. The data is only decoded
+// once everything before it checks out. The whole file is read first, so that
+// a corrupt length can't ask for more than the file holds.
+const MAGIC: &[u8; 8] = b"HTCACHE\0";
+
+// Bump whenever the layout of anything that gets cached changes.
+const CACHE_VERSION: u32 = 11;
+
+#[derive(Debug)]
+pub enum CacheError {
+    // Nothing has been cached yet
+    Missing,
+    // Built by another version, or from something else
+    Outdated(String),
+    Corrupt(String),
+    // Reading or writing the cache failed, e.g. for lack of permission
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CacheError::Missing => write!(f, "cache is missing"),
+            CacheError::Outdated(ref why) => write!(f, "{}", why),
+            CacheError::Corrupt(ref why) => write!(f, "cache is corrupt: {}", why),
+            CacheError::Io(ref path, ref e) => write!(f, "couldn't use {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl Error for CacheError {}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+fn corrupt(e: bincode::Error) -> CacheError {
+    CacheError::Corrupt(e.to_string())
+}
+
+/// Decodes the value at the start of `bytes`, and moves past it.
+fn take<T: Serialize + DeserializeOwned>(bytes: &mut &[u8]) -> Result<T, CacheError> {
+    let value = deserialize(bytes).map_err(corrupt)?;
+    let size = serialized_size(&value).map_err(corrupt)? as usize;
+    *bytes = &bytes[size..];
+    Ok(value)
+}
+
+/// Reads the cache at `path`, failing if it's corrupt, from another version,
+/// or was built from something other than `meta`.
+pub fn read<M, T>(path: &Path, meta: &M) -> Result<T, CacheError>
+where
+    M: Serialize + DeserializeOwned + PartialEq,
+    T: DeserializeOwned,
+{
+    let io_error = |e| CacheError::Io(path.to_owned(), e);
+    let mut bytes = vec![];
+    match File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes).map_err(io_error)?,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Err(CacheError::Missing),
+        Err(e) => return Err(io_error(e)),
+    };
+
+    if !bytes.starts_with(MAGIC) {
+        return Err(CacheError::Corrupt("not a cache file".to_owned()));
+    }
+    let mut rest = &bytes[MAGIC.len()..];
+    let version: u32 = take(&mut rest)?;
+    if version != CACHE_VERSION {
+        return Err(CacheError::Outdated(format!("cache is from version {}", version)));
+    }
+    let cached_meta: M = take(&mut rest)?;
+    if cached_meta != *meta {
+        return Err(CacheError::Outdated("cache is stale".to_owned()));
+    }
+
+    let expected: u64 = take(&mut rest)?;
+    if checksum(rest) != expected {
+        return Err(CacheError::Corrupt("checksum doesn't match".to_owned()));
+    }
+    deserialize(rest).map_err(corrupt)
+}
+
+pub fn write<M, T>(path: &Path, meta: &M, data: &T) -> Result<(), CacheError>
+where
+    M: Serialize,
+    T: Serialize,
+{
+    let io_error = |e| CacheError::Io(path.to_owned(), e);
+    let encode_error = |e: bincode::Error| io_error(io::Error::other(e));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let data = serialize(data).map_err(encode_error)?;
+    let mut file = BufWriter::new(File::create(path).map_err(io_error)?);
+    file.write_all(MAGIC).map_err(io_error)?;
+    serialize_into(&mut file, &CACHE_VERSION).map_err(encode_error)?;
+    serialize_into(&mut file, meta).map_err(encode_error)?;
+    serialize_into(&mut file, &checksum(&data)).map_err(encode_error)?;
+    file.write_all(&data).map_err(io_error)?;
+    file.flush().map_err(io_error)
+}
+
+/// Reads the cache at `path`, or builds and writes it if it's missing, stale
+/// or corrupt. Failing to read or write it at all is an error, as is failing
+/// to build it.
+pub fn get_or_build<M, T, E, F>(path: &Path, meta: &M, build: F) -> Result<T, E>
+where
+    M: Serialize + DeserializeOwned + PartialEq,
+    T: Serialize + DeserializeOwned,
+    E: From<CacheError>,
+    F: FnOnce() -> Result<T, E>,
+{
+    match read(path, meta) {
+        Ok(data) => return Ok(data),
+        Err(CacheError::Missing) => println!("building {}", path.display()),
+        Err(e @ CacheError::Outdated(_)) => println!("rebuilding {}: {}", path.display(), e),
+        Err(e @ CacheError::Corrupt(_)) => {
+            eprintln!("warning: rebuilding {}: {}", path.display(), e)
+        }
+        Err(e) => return Err(e.into()),
+    }
+    let data = build()?;
+    write(path, meta, &data)?;
+    Ok(data)
+}
+
+/// A short, stable identifier for `meta`, for naming cache files.
+pub fn key<M: Serialize>(meta: &M) -> String {
+    let mut hasher = FnvHasher::default();
+    hasher.write(to_string(meta).unwrap().as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Hashes the contents of a file, or of every file in a directory.
+pub fn hash_path(path: &Path) -> io::Result<u64> {
+    let mut hasher = FnvHasher::default();
+    if path.is_dir() {
+        let mut files = vec![];
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        for file in files {
+            hasher.write(file.file_name().unwrap().to_string_lossy().as_bytes());
+            hash_file(&mut hasher, &file)?;
+        }
+    } else {
+        hash_file(&mut hasher, path)?;
+    }
+    Ok(hasher.finish())
+}
+
+/// Hashes the name, size and modification time of a file, or of every file in
+/// a directory. Much cheaper than `hash_path` for big feeds, and still changes
+/// whenever a file is replaced or edited.
+pub fn stamp_path(path: &Path) -> io::Result<u64> {
+    let mut hasher = FnvHasher::default();
+    if path.is_dir() {
+        let mut files = vec![];
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        for file in files {
+            hasher.write(file.file_name().unwrap().to_string_lossy().as_bytes());
+            stamp_file(&mut hasher, &file)?;
+        }
+    } else {
+        stamp_file(&mut hasher, path)?;
+    }
+    Ok(hasher.finish())
+}
+
+fn stamp_file(hasher: &mut FnvHasher, path: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.write_u64(metadata.len());
+    hasher.write_u64(modified.as_secs());
+    hasher.write_u32(modified.subsec_nanos());
+    Ok(())
+}
+
+fn hash_file(hasher: &mut FnvHasher, path: &Path) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.write(&buf[..read]);
+    }
+}
+
+#[test]
+fn rebuilds_broken_caches() {
+    use std::{env, process};
+    let path = env::temp_dir().join(format!("heat-transit-cache-{}", process::id()));
+    let meta = ("feed.zip".to_owned(), Some(20240106u32));
+    let data: Vec<(String, f32)> = vec![("a".to_owned(), 1.5), ("bc".to_owned(), -2.0)];
+    write(&path, &meta, &data).unwrap();
+    let good = fs::read(&path).unwrap();
+
+    // Gets the cache, checking that it had to be rebuilt and is whole again.
+    let rebuilt = |bytes: &[u8]| {
+        fs::write(&path, bytes).unwrap();
+        let mut built = false;
+        let got: Result<Vec<(String, f32)>, CacheError> = get_or_build(&path, &meta, || {
+            built = true;
+            Ok(data.clone())
+        });
+        assert_eq!(got.unwrap(), data);
+        assert_eq!(fs::read(&path).unwrap(), good);
+        built
+    };
+
+    assert!(!rebuilt(&good));
+    // Every byte matters, from the magic number to the end of the data.
+    for i in 0..good.len() {
+        let mut flipped = good.clone();
+        flipped[i] ^= 0x40;
+        assert!(rebuilt(&flipped), "byte {} was flipped", i);
+    }
+    for len in 0..good.len() {
+        assert!(rebuilt(&good[..len]), "cut to {} bytes", len);
+    }
+    // From an older version, or not a cache at all
+    let mut old = MAGIC.to_vec();
+    old.extend(serialize(&(CACHE_VERSION - 1)).unwrap());
+    old.extend(&good[MAGIC.len() + 4..]);
+    fs::write(&path, &old).unwrap();
+    match read::<_, Vec<(String, f32)>>(&path, &meta) {
+        Err(CacheError::Outdated(_)) => {}
+        other => panic!("expected an outdated cache, got {:?}", other.map(|_| ())),
+    }
+    assert!(rebuilt(&old));
+    let mut other_magic = good.clone();
+    other_magic[..MAGIC.len()].copy_from_slice(b"NOTCACHE");
+    assert!(rebuilt(&other_magic));
+    fs::remove_file(&path).unwrap();
+}
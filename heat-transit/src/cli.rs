@@ -0,0 +1,760 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use colormap::{ColorMap, Ramp, Scale};
+use draw::RenderSettings;
+use model::{parse_time, FeedOptions};
+use projection::ProjectionKind;
+use std::path::PathBuf;
+use time::{CostModel, WaitStrategy};
+
+// Places are given as latitude and longitude, since they can only be projected
+// once the feed is loaded.
+pub enum Command {
+    BuildCache {
+        destination: (f64, f64),
+        cost_model: CostModel,
+        // OpenStreetMap extract to walk along
+        streets: Option<PathBuf>,
+    },
+    Render {
+        destination: (f64, f64),
+        // Seconds since the start of the service day
+        departure: Option<u64>,
+        // Search once backwards from the destination rather than once per pixel
+        reverse: bool,
+        // Draw how much the Pareto-optimal route with the fewest transfers costs
+        // over the fastest
+        pareto: bool,
+        cost_model: CostModel,
+        streets: Option<PathBuf>,
+        settings: RenderSettings,
+    },
+    Route {
+        origin: (f64, f64),
+        destination: (f64, f64),
+        // Seconds since the start of the service day
+        departure: Option<u64>,
+        // Where to also write the itinerary as JSON
+        json: Option<PathBuf>,
+        // List every Pareto-optimal route instead of the single best one
+        pareto: bool,
+        cost_model: CostModel,
+        streets: Option<PathBuf>,
+    },
+    Compare {
+        destination: (f64, f64),
+        // The scenario to compare against: another feed, another destination,
+        // or both
+        against_data: Option<PathBuf>,
+        against_destination: Option<(f64, f64)>,
+        // Minutes a change has to be bigger than to count in the summary
+        threshold: f32,
+        cost_model: CostModel,
+        settings: RenderSettings,
+    },
+    Animate {
+        destination: (f64, f64),
+        // Seconds since the start of the service day
+        start: u64,
+        end: u64,
+        // Seconds between frames, and how long after each frame's time a bus
+        // can still be boarded
+        every: u64,
+        window: u64,
+        // Milliseconds each frame is shown for
+        delay: u32,
+        cost_model: CostModel,
+        settings: RenderSettings,
+    },
+    // List everything in the feed that had to be skipped
+    Validate,
+    // Compare A* with Dijkstra from some origins, to catch a heuristic that
+    // over-estimates
+    CheckHeuristic {
+        destination: (f64, f64),
+        samples: usize,
+        cost_model: CostModel,
+        streets: Option<PathBuf>,
+    },
+    Accessibility {
+        destinations: PathBuf,
+        // Minutes
+        within: f32,
+        cost_model: CostModel,
+        settings: RenderSettings,
+    },
+}
+
+pub struct Options {
+    pub feed: FeedOptions,
+    pub command: Command,
+}
+
+fn parse_lat_lon(s: &str) -> Result<(f64, f64), String> {
+    let mut split = s.split(',');
+    let lat = split.next().and_then(|lat| lat.trim().parse().ok());
+    let lon = split.next().and_then(|lon| lon.trim().parse().ok());
+    match (lat, lon, split.next()) {
+        (Some(lat), Some(lon), None) => Ok((lat, lon)),
+        _ => Err(format!("expected LAT,LON but got '{}'", s)),
+    }
+}
+
+fn parse_clock(s: &str) -> Result<u64, String> {
+    parse_time(s).ok_or_else(|| format!("expected HH:MM:SS but got '{}'", s))
+}
+
+fn parse_window(s: &str) -> Result<(u64, u64), String> {
+    let mut split = s.splitn(2, '-');
+    let from = parse_clock(split.next().unwrap())?;
+    let until = split
+        .next()
+        .ok_or_else(|| format!("expected HH:MM:SS-HH:MM:SS but got '{}'", s))
+        .and_then(parse_clock)?;
+    if until <= from {
+        return Err(format!("'{}' ends before it starts", s));
+    }
+    Ok((from, until))
+}
+
+fn parse_wait(s: &str) -> Result<WaitStrategy, String> {
+    match s {
+        "headway" => Ok(WaitStrategy::HalfHeadway),
+        minutes => Ok(WaitStrategy::Constant(parse_non_negative(minutes)? * 60.0)),
+    }
+}
+
+fn validate<T, F>(f: F) -> impl Fn(String) -> Result<(), String>
+where
+    F: Fn(&str) -> Result<T, String> + 'static,
+{
+    move |s| f(&s).map(|_| ())
+}
+
+fn parse_number<T: ::std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("'{}' is not a valid number", s))
+}
+
+fn parse_non_negative(s: &str) -> Result<f32, String> {
+    let n: f32 = parse_number(s)?;
+    if n >= 0.0 {
+        Ok(n)
+    } else {
+        Err(format!("'{}' can't be negative", s))
+    }
+}
+
+fn parse_positive<T: ::std::str::FromStr + Into<f64> + Copy>(s: &str) -> Result<T, String> {
+    let n: T = parse_number(s)?;
+    if n.into().is_finite() && n.into() > 0.0 {
+        Ok(n)
+    } else {
+        Err(format!("'{}' isn't a positive number", s))
+    }
+}
+
+fn lat_lon(matches: &ArgMatches, name: &str) -> (f64, f64) {
+    parse_lat_lon(matches.value_of(name).unwrap()).unwrap()
+}
+
+fn clock(matches: &ArgMatches, name: &str) -> u64 {
+    parse_clock(matches.value_of(name).unwrap()).unwrap()
+}
+
+fn number<T: ::std::str::FromStr>(matches: &ArgMatches, name: &str) -> T {
+    parse_number(matches.value_of(name).unwrap()).unwrap()
+}
+
+fn color_map(matches: &ArgMatches) -> ColorMap {
+    ColorMap {
+        ramp: match matches.value_of("colormap").unwrap() {
+            "magma" => Ramp::Magma,
+            "components" => Ramp::Components,
+            _ => Ramp::Viridis,
+        },
+        scale: match matches.value_of("scale").unwrap() {
+            "log" => Scale::Log,
+            _ => Scale::Linear,
+        },
+        bands: matches.value_of("bands").map(|b| b.parse().unwrap()),
+        max_minutes: matches.value_of("max-minutes").map(|m| m.parse().unwrap()),
+    }
+}
+
+fn cost_model(matches: &ArgMatches) -> CostModel {
+    CostModel {
+        walk_weight: number(matches, "walk-weight"),
+        ride_weight: number(matches, "ride-weight"),
+        wait_weight: number(matches, "wait-weight"),
+        transfer_penalty: number::<f32>(matches, "transfer-penalty") * 60.0,
+        ..limit_model(matches)
+    }
+}
+
+/// A cost model from just the `limit_args`, for commands that go by plain
+/// travel time.
+fn limit_model(matches: &ArgMatches) -> CostModel {
+    CostModel {
+        max_transfers: matches.value_of("max-transfers").map(|m| m.parse().unwrap()),
+        wait: parse_wait(matches.value_of("wait").unwrap()).unwrap(),
+        window: matches.value_of("time-window").map(|w| parse_window(w).unwrap()),
+        ..CostModel::default()
+    }
+}
+
+fn render_settings(matches: &ArgMatches) -> RenderSettings {
+    RenderSettings {
+        size: number(matches, "resolution"),
+        extent: number(matches, "extent"),
+        output: PathBuf::from(matches.value_of("output").unwrap()),
+        colors: color_map(matches),
+        legend: !matches.is_present("no-legend"),
+        contours: matches
+            .values_of("contours")
+            .map(|contours| contours.map(|minutes| minutes.parse().unwrap()).collect())
+            .unwrap_or_default(),
+        geojson: matches.value_of("geojson").map(PathBuf::from),
+        svg: matches.value_of("svg").map(PathBuf::from),
+        stats: matches.value_of("stats").map(PathBuf::from),
+        population: matches.value_of("population").map(PathBuf::from),
+        stats_bin: matches.value_of("stats-bin").map_or(5.0, |b| b.parse().unwrap()),
+        tolerance: matches.value_of("tolerance").map(|t| t.parse().unwrap()),
+    }
+}
+
+fn destination_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("to")
+        .long("to")
+        .value_name("LAT,LON")
+        .help("Where everyone is travelling to")
+        .required(true)
+        .allow_hyphen_values(true)
+        .validator(validate(parse_lat_lon))
+}
+
+// The scheduled search only looks for the earliest arrival, so none of the
+// cost arguments would change what it finds.
+fn departure_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("depart-at")
+        .long("depart-at")
+        .value_name("HH:MM:SS")
+        .help("Route using the scheduled departures, leaving at this time")
+        .validator(validate(parse_clock))
+        .conflicts_with_all(&[
+            "walk-weight",
+            "ride-weight",
+            "wait-weight",
+            "transfer-penalty",
+            "max-transfers",
+            "wait",
+            "time-window",
+        ])
+}
+
+fn image_args<'a, 'b>(output: &'a str) -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("extent")
+            .long("extent")
+            .value_name("KM")
+            .help("Distance from the center of the map to its edges")
+            .default_value("15")
+            .validator(validate(parse_positive::<f64>)),
+        Arg::with_name("resolution")
+            .long("resolution")
+            .value_name("PIXELS")
+            .help("Width and height of the image")
+            .default_value("1000")
+            .validator(validate(parse_positive::<u32>)),
+        Arg::with_name("output")
+            .long("output")
+            .short("o")
+            .value_name("FILE")
+            .help("Where to write the image")
+            .default_value(output),
+        Arg::with_name("colormap")
+            .long("colormap")
+            .help("How the map is colored")
+            .possible_values(&["viridis", "magma", "components"])
+            .default_value("viridis"),
+        Arg::with_name("scale")
+            .long("scale")
+            .help("How values map onto the colormap")
+            .possible_values(&["linear", "log"])
+            .default_value("linear"),
+        Arg::with_name("bands")
+            .long("bands")
+            .value_name("MINUTES")
+            .help("Color in discrete bands this many minutes wide")
+            .validator(validate(parse_number::<f32>)),
+        Arg::with_name("max-minutes")
+            .long("max-minutes")
+            .value_name("MINUTES")
+            .help("Travel time at the end of the colormap [default: the slowest on the map]")
+            .validator(validate(parse_number::<f32>)),
+        Arg::with_name("no-legend")
+            .long("no-legend")
+            .help("Don't draw the legend"),
+    ]
+}
+
+fn tolerance_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("tolerance")
+        .long("tolerance")
+        .value_name("MINUTES")
+        .help(
+            "Only search from some pixels, interpolating between them wherever travel times \
+             differ by less than this",
+        )
+        .validator(validate(parse_number::<f32>))
+}
+
+fn cost_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = weight_args();
+    args.extend(limit_args());
+    args
+}
+
+// How much each part of a trip counts for
+fn weight_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("walk-weight")
+            .long("walk-weight")
+            .value_name("WEIGHT")
+            .help("How much worse a minute of walking is than a minute on the bus")
+            .default_value("1")
+            .validator(validate(parse_non_negative)),
+        Arg::with_name("ride-weight")
+            .long("ride-weight")
+            .value_name("WEIGHT")
+            .help("How much a minute on the bus counts")
+            .default_value("1")
+            .validator(validate(parse_non_negative)),
+        Arg::with_name("wait-weight")
+            .long("wait-weight")
+            .value_name("WEIGHT")
+            .help("How much worse a minute of waiting is than a minute on the bus")
+            .default_value("1")
+            .validator(validate(parse_non_negative)),
+        Arg::with_name("transfer-penalty")
+            .long("transfer-penalty")
+            .value_name("MINUTES")
+            .help("Extra minutes every transfer counts for")
+            .default_value("0")
+            .validator(validate(parse_non_negative)),
+    ]
+}
+
+// Which trips can be taken at all, and how long they take
+fn limit_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("max-transfers")
+            .long("max-transfers")
+            .value_name("N")
+            .help("Never transfer more than this many times")
+            .validator(validate(parse_number::<u32>)),
+        Arg::with_name("wait")
+            .long("wait")
+            .value_name("MINUTES")
+            .help(
+                "How long to expect to wait for a bus, or 'headway' for half the time between \
+                 its departures",
+            )
+            .default_value("headway")
+            .validator(validate(parse_wait)),
+    ]
+}
+
+// Animations pick their own window for every frame, so this is kept apart from
+// the rest of the cost arguments.
+fn time_window_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("time-window")
+        .long("time-window")
+        .value_name("HH:MM:SS-HH:MM:SS")
+        .help(
+            "Only count buses leaving in this part of the day towards headways \
+             [default: all day]",
+        )
+        .validator(validate(parse_window))
+}
+
+fn streets_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("streets")
+        .long("streets")
+        .value_name("FILE")
+        .help("OpenStreetMap XML extract to walk along, instead of walking in straight lines")
+}
+
+fn pareto_arg<'a, 'b>(help: &'b str) -> Arg<'a, 'b> {
+    Arg::with_name("pareto")
+        .long("pareto")
+        .help(help)
+        .conflicts_with("depart-at")
+}
+
+fn contour_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("contours")
+            .long("contours")
+            .value_name("MINUTES")
+            .help("Travel times to draw isochrones at")
+            .use_delimiter(true)
+            .default_value("15,30,45,60")
+            .validator(validate(parse_positive::<f32>)),
+        Arg::with_name("geojson")
+            .long("geojson")
+            .value_name("FILE")
+            .help("Also write the isochrones as GeoJSON"),
+        Arg::with_name("svg")
+            .long("svg")
+            .value_name("FILE")
+            .help("Also write the isochrones as an SVG overlay"),
+    ]
+}
+
+fn stats_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("stats")
+            .long("stats")
+            .value_name("FILE")
+            .help("Also write how much is reachable in how long, as CSV or JSON by extension"),
+        Arg::with_name("population")
+            .long("population")
+            .value_name("FILE")
+            .help("CSV of census blocks with lat, lon and population columns to weigh --stats by")
+            .requires("stats"),
+        Arg::with_name("stats-bin")
+            .long("stats-bin")
+            .value_name("MINUTES")
+            .help("Width of the travel time bins in --stats")
+            .default_value("5")
+            .validator(validate(|s| match parse_number::<f32>(s)? {
+                n if n > 0.0 => Ok(n),
+                _ => Err("bins have to be wider than 0 minutes".to_owned()),
+            })),
+    ]
+}
+
+pub fn parse() -> Options {
+    let matches = App::new("transit-times")
+        .about("Heat maps of how long it takes to get somewhere on transit")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("data")
+                .long("data")
+                .value_name("PATH")
+                .help("GTFS feed, either a directory or a zip file")
+                .default_value("data")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("cache")
+                .long("cache")
+                .value_name("DIR")
+                .help("Where to keep preprocessed data")
+                .default_value("cache")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("date")
+                .long("date")
+                .value_name("YYYYMMDD")
+                .help("Only use trips that run on this day")
+                .validator(validate(parse_number::<u32>))
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("projection")
+                .long("projection")
+                .help("How latitude and longitude are flattened, around the middle of the map")
+                .possible_values(&["transverse-mercator", "equirectangular"])
+                .default_value("transverse-mercator")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("center")
+                .long("center")
+                .value_name("LAT,LON")
+                .help(
+                    "Where the middle of the map is, e.g. 47.6,-122.33 for downtown Seattle \
+                     [default: the middle of the feed's stops]",
+                )
+                .allow_hyphen_values(true)
+                .validator(validate(parse_lat_lon))
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("List the rows and trips of the feed that can't be used"),
+        )
+        .subcommand(
+            SubCommand::with_name("check-heuristic")
+                .about(
+                    "Check that routing with the route cache finds the same routes as \
+                     searching without it",
+                )
+                .arg(destination_arg())
+                .arg(
+                    Arg::with_name("samples")
+                        .long("samples")
+                        .value_name("N")
+                        .help("How many stops to search from")
+                        .default_value("100")
+                        .validator(validate(parse_number::<usize>)),
+                )
+                .arg(streets_arg())
+                .args(&cost_args())
+                .arg(time_window_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("build-cache")
+                .about("Preprocess the feed and the route cache for a destination")
+                .arg(destination_arg())
+                .arg(streets_arg())
+                .args(&cost_args())
+                .arg(time_window_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("render")
+                .about("Render a map of travel times to a destination")
+                .arg(destination_arg())
+                .arg(departure_arg())
+                .arg(
+                    Arg::with_name("reverse")
+                        .long("reverse")
+                        .help(
+                            "Search once backwards from the destination instead of from every \
+                             pixel",
+                        )
+                        .conflicts_with("depart-at"),
+                )
+                .arg(pareto_arg(
+                    "Draw how much longer the route with the fewest transfers takes than the \
+                     fastest one, within --max-transfers",
+                ).conflicts_with("reverse"))
+                .arg(streets_arg().conflicts_with_all(&["depart-at", "reverse", "pareto"]))
+                .args(&cost_args())
+                .arg(time_window_arg())
+                .args(&image_args("out/out.png"))
+                .arg(tolerance_arg())
+                .args(&contour_args())
+                .args(&stats_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about(
+                    "Render how travel times change in another scenario, from blue where \
+                     it's faster to red where it's slower",
+                )
+                .arg(destination_arg())
+                .arg(
+                    Arg::with_name("against-data")
+                        .long("against-data")
+                        .value_name("PATH")
+                        .help("GTFS feed to compare against, cached in a subdirectory of --cache")
+                        .required_unless("against-to"),
+                )
+                .arg(
+                    Arg::with_name("against-to")
+                        .long("against-to")
+                        .value_name("LAT,LON")
+                        .help("Destination to compare against")
+                        .allow_hyphen_values(true)
+                        .validator(validate(parse_lat_lon)),
+                )
+                .arg(
+                    Arg::with_name("threshold")
+                        .long("threshold")
+                        .value_name("MINUTES")
+                        .help("How big a change has to be to count in the summary")
+                        .default_value("5")
+                        .validator(validate(parse_number::<f32>)),
+                )
+                .args(&cost_args())
+                .arg(time_window_arg())
+                .args(&image_args("out/compare.png"))
+                .arg(tolerance_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("animate")
+                .about("Render an animation of how travel times change over the day")
+                .arg(destination_arg())
+                .arg(
+                    Arg::with_name("start")
+                        .long("start")
+                        .value_name("HH:MM:SS")
+                        .help("Time of the first frame")
+                        .default_value("06:00:00")
+                        .validator(validate(parse_clock)),
+                )
+                .arg(
+                    Arg::with_name("end")
+                        .long("end")
+                        .value_name("HH:MM:SS")
+                        .help("Time of the last frame")
+                        .default_value("23:00:00")
+                        .validator(validate(parse_clock)),
+                )
+                .arg(
+                    Arg::with_name("every")
+                        .long("every")
+                        .value_name("MINUTES")
+                        .help("Time between frames")
+                        .default_value("15")
+                        .validator(validate(|s| match parse_number::<u64>(s)? {
+                            0 => Err("there has to be time between frames".to_owned()),
+                            n => Ok(n),
+                        })),
+                )
+                .arg(
+                    Arg::with_name("window")
+                        .long("window")
+                        .value_name("MINUTES")
+                        .help(
+                            "Only use buses that can be boarded this long after each frame's \
+                             time",
+                        )
+                        .default_value("60")
+                        .validator(validate(parse_number::<u64>)),
+                )
+                .arg(
+                    Arg::with_name("frame-delay")
+                        .long("frame-delay")
+                        .value_name("MS")
+                        .help("How long each frame is shown for")
+                        .default_value("500")
+                        .validator(validate(parse_number::<u32>)),
+                )
+                .args(&cost_args())
+                .args(&image_args("out/out.gif"))
+                .arg(tolerance_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("accessibility")
+                .about("Render a map of how many destinations can be reached in time")
+                .arg(
+                    Arg::with_name("destinations")
+                        .long("destinations")
+                        .value_name("FILE")
+                        .help("CSV of destinations with name, lat and lon columns")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("within")
+                        .long("within")
+                        .value_name("MINUTES")
+                        .help("How long people are willing to travel")
+                        .default_value("30")
+                        .validator(validate(parse_number::<f32>)),
+                )
+                .args(&limit_args())
+                .arg(time_window_arg())
+                .args(&image_args("out/out.png")),
+        )
+        .subcommand(
+            SubCommand::with_name("route")
+                .about("Find the fastest way between two places")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("LAT,LON")
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .validator(validate(parse_lat_lon)),
+                )
+                .arg(destination_arg())
+                .arg(departure_arg())
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .value_name("FILE")
+                        .help("Also write the itinerary as JSON"),
+                )
+                .arg(pareto_arg(
+                    "List every route that's better than the rest at one of travel time, \
+                     walking or transfers",
+                ).conflicts_with("json"))
+                .arg(streets_arg().conflicts_with_all(&["depart-at", "pareto"]))
+                .args(&cost_args())
+                .arg(time_window_arg()),
+        )
+        .get_matches();
+
+    // Global arguments are propagated down to the subcommand's matches.
+    let (name, m) = match matches.subcommand() {
+        (name, Some(m)) => (name, m),
+        _ => unreachable!(),
+    };
+
+    let feed = FeedOptions::new(
+        PathBuf::from(m.value_of("data").unwrap()),
+        PathBuf::from(m.value_of("cache").unwrap()),
+        m.value_of("date").map(|d| d.parse().unwrap()),
+        match m.value_of("projection").unwrap() {
+            "equirectangular" => ProjectionKind::Equirectangular,
+            _ => ProjectionKind::TransverseMercator,
+        },
+        m.value_of("center").map(|_| lat_lon(m, "center")),
+    );
+
+    let command = match name {
+        "validate" => Command::Validate,
+        "check-heuristic" => Command::CheckHeuristic {
+            destination: lat_lon(m, "to"),
+            samples: number(m, "samples"),
+            cost_model: cost_model(m),
+            streets: m.value_of("streets").map(PathBuf::from),
+        },
+        "build-cache" => Command::BuildCache {
+            destination: lat_lon(m, "to"),
+            cost_model: cost_model(m),
+            streets: m.value_of("streets").map(PathBuf::from),
+        },
+        "render" => Command::Render {
+            destination: lat_lon(m, "to"),
+            departure: m.value_of("depart-at").map(|_| clock(m, "depart-at")),
+            reverse: m.is_present("reverse"),
+            pareto: m.is_present("pareto"),
+            cost_model: cost_model(m),
+            streets: m.value_of("streets").map(PathBuf::from),
+            settings: render_settings(m),
+        },
+        "compare" => Command::Compare {
+            destination: lat_lon(m, "to"),
+            against_data: m.value_of("against-data").map(PathBuf::from),
+            against_destination: m.value_of("against-to").map(|_| lat_lon(m, "against-to")),
+            threshold: number(m, "threshold"),
+            cost_model: cost_model(m),
+            settings: render_settings(m),
+        },
+        "animate" => Command::Animate {
+            destination: lat_lon(m, "to"),
+            start: clock(m, "start"),
+            end: clock(m, "end"),
+            every: number::<u64>(m, "every") * 60,
+            window: number::<u64>(m, "window") * 60,
+            delay: number(m, "frame-delay"),
+            cost_model: cost_model(m),
+            settings: render_settings(m),
+        },
+        "accessibility" => Command::Accessibility {
+            destinations: PathBuf::from(m.value_of("destinations").unwrap()),
+            within: number(m, "within"),
+            cost_model: limit_model(m),
+            settings: render_settings(m),
+        },
+        "route" => Command::Route {
+            origin: lat_lon(m, "from"),
+            destination: lat_lon(m, "to"),
+            departure: m.value_of("depart-at").map(|_| clock(m, "depart-at")),
+            json: m.value_of("json").map(PathBuf::from),
+            pareto: m.is_present("pareto"),
+            cost_model: cost_model(m),
+            streets: m.value_of("streets").map(PathBuf::from),
+        },
+        _ => unreachable!(),
+    };
+
+    Options {
+        feed: feed,
+        command: command,
+    }
+}
@@ -0,0 +1,282 @@
+use font;
+use time::TimeCost;
+
+// Evenly spaced samples of matplotlib's perceptually uniform colormaps.
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [72, 40, 120],
+    [62, 73, 137],
+    [49, 104, 142],
+    [38, 130, 142],
+    [31, 158, 137],
+    [53, 183, 121],
+    [110, 206, 88],
+    [253, 231, 37],
+];
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 89, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+// ColorBrewer's RdBu, from blue for faster through white to red for slower.
+const DIVERGING: [[u8; 3]; 5] = [
+    [33, 102, 172],
+    [146, 197, 222],
+    [247, 247, 247],
+    [244, 165, 130],
+    [178, 24, 43],
+];
+
+// Times shorter than this are all the same color on a log scale.
+const LOG_FLOOR_MINUTES: f32 = 1.0;
+
+const BLACK: [u8; 4] = [0, 0, 0, 255];
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ramp {
+    Viridis,
+    Magma,
+    // Walking, riding and waiting times in the red, green and blue channels,
+    // each scaled independently.
+    Components,
+    // For changes in travel time, centered on no change.
+    Diverging,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scale {
+    Linear,
+    Log,
+}
+
+pub struct ColorMap {
+    pub ramp: Ramp,
+    pub scale: Scale,
+    // Quantize travel times into bands this many minutes wide
+    pub bands: Option<f32>,
+    // Anything slower than this gets the last color; defaults to the slowest
+    // time on the map
+    pub max_minutes: Option<f32>,
+}
+
+fn interpolate(stops: &[[u8; 3]], t: f32) -> [u8; 4] {
+    let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (t.floor() as usize).min(stops.len() - 2);
+    let frac = t - i as f32;
+    let mut out = [255; 4];
+    for c in 0..3 {
+        let (a, b) = (stops[i][c] as f32, stops[i + 1][c] as f32);
+        out[c] = (a + (b - a) * frac).round() as u8;
+    }
+    out
+}
+
+impl ColorMap {
+    /// The travel times in minutes that the colors span.
+    pub fn range(&self, grid: &[Option<TimeCost>]) -> (f32, f32) {
+        let minutes = grid.iter().filter_map(|x| x.map(|x| x.total() / 60.0));
+        let (min, max) = minutes.fold((1.0 / 0.0, -1.0 / 0.0), |(min, max): (f32, f32), m| {
+            (min.min(m), max.max(m))
+        });
+        if min > max {
+            return (0.0, 1.0);
+        }
+        let max = self.max_minutes.unwrap_or(max);
+        (min.min(max), max)
+    }
+
+    /// Where along the ramp a travel time falls, from 0 to 1.
+    fn position(&self, minutes: f32, (min, max): (f32, f32)) -> f32 {
+        let minutes = match self.bands {
+            Some(width) => ((minutes / width).floor() + 0.5) * width,
+            None => minutes,
+        };
+        let t = match self.scale {
+            Scale::Linear => (minutes - min) / (max - min),
+            Scale::Log => {
+                let floor = |m: f32| m.max(LOG_FLOOR_MINUTES).ln();
+                (floor(minutes) - floor(min)) / (floor(max) - floor(min))
+            }
+        };
+        if t.is_finite() {
+            t.clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn color(&self, minutes: f32, range: (f32, f32)) -> [u8; 4] {
+        let t = self.position(minutes, range);
+        match self.ramp {
+            Ramp::Viridis => interpolate(&VIRIDIS, t),
+            Ramp::Magma => interpolate(&MAGMA, t),
+            Ramp::Components => interpolate(&[[0, 0, 0], [255, 255, 255]], t),
+            Ramp::Diverging => interpolate(&DIVERGING, t),
+        }
+    }
+
+    /// Colors every pixel of the grid into an RGBA image.
+    pub fn paint(&self, grid: &[Option<TimeCost>]) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(grid.len() * 4);
+        if self.ramp == Ramp::Components {
+            for pix in normalize(grid) {
+                if let Some(pix) = pix {
+                    pixels.push((pix.walk_time * 255.0) as u8);
+                    pixels.push((pix.bus_time * 255.0) as u8);
+                    pixels.push((pix.wait_time * 255.0) as u8);
+                    pixels.push(255);
+                } else {
+                    pixels.extend_from_slice(&TRANSPARENT);
+                }
+            }
+            return pixels;
+        }
+
+        let minutes: Vec<Option<f32>> = grid.iter().map(|x| x.map(|x| x.total() / 60.0)).collect();
+        self.paint_values(&minutes, self.range(grid))
+    }
+
+    /// Colors arbitrary values (travel times in minutes, or scores) into an
+    /// RGBA image.
+    pub fn paint_values(&self, values: &[Option<f32>], range: (f32, f32)) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(values.len() * 4);
+        for value in values {
+            match *value {
+                Some(value) => pixels.extend_from_slice(&self.color(value, range)),
+                None => pixels.extend_from_slice(&TRANSPARENT),
+            }
+        }
+        pixels
+    }
+
+    /// Draws a labelled color bar into the bottom left of the image.
+    pub fn draw_legend(
+        &self,
+        pixels: &mut [u8],
+        width: u32,
+        height: u32,
+        range: (f32, f32),
+        unit: &str,
+    ) {
+        if self.ramp == Ramp::Components {
+            // There's no single scale to show.
+            return;
+        }
+
+        let scale = (width / 400).max(1);
+        let margin = 8 * scale;
+        let bar_width = width / 3;
+        let bar_height = 6 * scale;
+        let text_height = font::text_height(scale);
+        let box_width = bar_width + font::text_width(unit, scale) + 4 * margin;
+        let box_height = bar_height + 2 * scale + text_height + 4 * margin;
+        if box_width > width || box_height > height {
+            return;
+        }
+        let (box_x, box_y) = (margin, height - margin - box_height);
+        fill(pixels, width, (box_x, box_y), (box_width, box_height), WHITE);
+
+        let (bar_x, bar_y) = (box_x + 2 * margin, box_y + margin + scale);
+        for x in 0..bar_width {
+            let t = x as f32 / (bar_width - 1).max(1) as f32;
+            let minutes = self.minutes_at(t, range);
+            let color = self.color(minutes, range);
+            fill(pixels, width, (bar_x + x, bar_y), (1, bar_height), color);
+        }
+        let label_y = bar_y + bar_height + 2 * scale;
+        font::draw_text(pixels, width, (bar_x + bar_width + margin / 2, bar_y), scale, unit, BLACK);
+
+        let mut last_label_end = 0;
+        for minutes in ticks(range) {
+            let position = self.position_unbanded(minutes as f32, range);
+            let x = bar_x + (position * (bar_width - 1) as f32) as u32;
+            fill(pixels, width, (x, bar_y + bar_height), (scale, 2 * scale), BLACK);
+
+            let label = format!("{}", minutes);
+            let label_width = font::text_width(&label, scale);
+            let label_x = x.saturating_sub(label_width / 2);
+            if label_x < last_label_end + scale * 2 && last_label_end != 0 {
+                continue;
+            }
+            font::draw_text(pixels, width, (label_x, label_y + scale), scale, &label, BLACK);
+            last_label_end = label_x + label_width;
+        }
+    }
+
+    fn position_unbanded(&self, minutes: f32, range: (f32, f32)) -> f32 {
+        ColorMap {
+            bands: None,
+            ..*self
+        }.position(minutes, range)
+    }
+
+    /// The inverse of `position`, ignoring bands.
+    fn minutes_at(&self, t: f32, (min, max): (f32, f32)) -> f32 {
+        match self.scale {
+            Scale::Linear => min + (max - min) * t,
+            Scale::Log => {
+                let (lo, hi) = (min.max(LOG_FLOOR_MINUTES).ln(), max.max(LOG_FLOOR_MINUTES).ln());
+                (lo + (hi - lo) * t).exp()
+            }
+        }
+    }
+}
+
+/// Round numbers to label the legend with.
+fn ticks((min, max): (f32, f32)) -> Vec<i32> {
+    let steps = [1, 2, 5, 10, 15, 20, 30, 60, 90, 120, 180, 240, 360];
+    let step = *steps
+        .iter()
+        .find(|&&step| (max - min) / step as f32 <= 6.0)
+        .unwrap_or(&steps[steps.len() - 1]);
+    let first = (min / step as f32).ceil() as i32 * step;
+    (0..)
+        .map(|i| first + i * step)
+        .take_while(|&m| m as f32 <= max)
+        .collect()
+}
+
+pub fn fill(pixels: &mut [u8], width: u32, (x, y): (u32, u32), (w, h): (u32, u32), color: [u8; 4]) {
+    let height = pixels.len() as u32 / (width * 4);
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            let i = ((py * width + px) * 4) as usize;
+            pixels[i..i + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+fn normalize(data: &[Option<TimeCost>]) -> Vec<Option<TimeCost>> {
+    // TY: This is log2 vs. linear choice.
+    //for datum in data.iter_mut() {
+    //    *datum = datum.map(|x| x.log2());
+    //}
+    let min = data.iter()
+        .cloned()
+        .filter_map(|x| x)
+        .fold(TimeCost::with_all(1.0 / 0.0), ::std::cmp::min);
+    let max = data.iter()
+        .cloned()
+        .filter_map(|x| x)
+        .fold(TimeCost::with_all(-1.0 / 0.0), ::std::cmp::max);
+
+    data.iter()
+        .map(|x| {
+            x.map(|x| TimeCost {
+                walk_time: (x.walk_time - min.walk_time) / (max.walk_time - min.walk_time),
+                bus_time: (x.bus_time - min.bus_time) / (max.bus_time - min.bus_time),
+                wait_time: (x.wait_time - min.wait_time) / (max.wait_time - min.wait_time),
+                transfers: x.transfers,
+                generalized: x.generalized,
+            })
+        })
+        .collect()
+}
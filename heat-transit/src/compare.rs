@@ -0,0 +1,89 @@
+use draw::RenderSettings;
+use std::fmt;
+use time::TimeCost;
+
+/// How much of the map got faster or slower to reach between two renders, in
+/// square km.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    // Minutes a change has to be bigger than to count
+    pub threshold: f32,
+    pub faster: f64,
+    pub slower: f64,
+    // Only reachable in one of them
+    pub gained: f64,
+    pub lost: f64,
+    // Average change in minutes where both are reachable
+    pub mean_change: f32,
+}
+
+/// The change in minutes from `before` to `after` at every pixel, negative
+/// where it got faster. Places only reachable in one of them count as the
+/// biggest change the colors show, `max` minutes.
+pub fn difference(
+    before: &[Option<TimeCost>],
+    after: &[Option<TimeCost>],
+    max: f32,
+) -> Vec<Option<f32>> {
+    before
+        .iter()
+        .zip(after)
+        .map(|pair| match pair {
+            (&Some(before), &Some(after)) => Some((after.total() - before.total()) / 60.0),
+            (&None, &Some(_)) => Some(-max),
+            (&Some(_), &None) => Some(max),
+            (&None, &None) => None,
+        })
+        .collect()
+}
+
+pub fn summarize(
+    settings: &RenderSettings,
+    before: &[Option<TimeCost>],
+    after: &[Option<TimeCost>],
+    threshold: f32,
+) -> Summary {
+    let pixel_size = 2.0 * settings.extent / settings.size as f64;
+    let pixel_area = pixel_size * pixel_size;
+
+    let mut summary = Summary {
+        threshold: threshold,
+        faster: 0.0,
+        slower: 0.0,
+        gained: 0.0,
+        lost: 0.0,
+        mean_change: 0.0,
+    };
+    let (mut total_change, mut count) = (0.0, 0);
+    for pair in before.iter().zip(after) {
+        match pair {
+            (&Some(before), &Some(after)) => {
+                let change = (after.total() - before.total()) / 60.0;
+                if change < -threshold {
+                    summary.faster += pixel_area;
+                } else if change > threshold {
+                    summary.slower += pixel_area;
+                }
+                total_change += change;
+                count += 1;
+            }
+            (&None, &Some(_)) => summary.gained += pixel_area,
+            (&Some(_), &None) => summary.lost += pixel_area,
+            (&None, &None) => {}
+        }
+    }
+    if count > 0 {
+        summary.mean_change = total_change / count as f32;
+    }
+    summary
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Faster by over {} min: {:.2} km²", self.threshold, self.faster)?;
+        writeln!(f, "Slower by over {} min: {:.2} km²", self.threshold, self.slower)?;
+        writeln!(f, "Newly reachable: {:.2} km²", self.gained)?;
+        writeln!(f, "No longer reachable: {:.2} km²", self.lost)?;
+        write!(f, "Average change: {:+.1} min", self.mean_change)
+    }
+}
@@ -0,0 +1,430 @@
+use draw::RenderSettings;
+use fnv::FnvHashMap as HashMap;
+use projection::Projection;
+use serde_json::to_writer;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use time::TimeCost;
+
+// Identifies the crossing point on a grid edge. Horizontal edges run from
+// (x, y) to (x + 1, y), vertical edges from (x, y) to (x, y + 1).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Edge {
+    Horizontal(usize, usize),
+    Vertical(usize, usize),
+}
+
+/// The travel times as a grid padded with a border of unreachable cells, so
+/// that every contour ends up as a closed ring.
+struct Field {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl Field {
+    fn new(settings: &RenderSettings, grid: &[Option<TimeCost>]) -> Field {
+        let size = settings.size as usize;
+        let (width, height) = (size + 2, size + 2);
+        let mut values = vec![f32::INFINITY; width * height];
+        for y in 0..size {
+            for x in 0..size {
+                if let Some(cost) = grid[y * size + x] {
+                    values[(y + 1) * width + (x + 1)] = cost.total();
+                }
+            }
+        }
+        Field {
+            width: width,
+            height: height,
+            values: values,
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.values[y * self.width + x]
+    }
+
+    /// Where the contour crosses `edge`, in pixel coordinates of the unpadded grid.
+    fn crossing(&self, edge: Edge, level: f32) -> (f64, f64) {
+        let ((ax, ay), (bx, by)) = match edge {
+            Edge::Horizontal(x, y) => ((x, y), (x + 1, y)),
+            Edge::Vertical(x, y) => ((x, y), (x, y + 1)),
+        };
+        let (a, b) = (self.get(ax, ay), self.get(bx, by));
+        let t = if a.is_finite() && b.is_finite() {
+            ((level - a) / (b - a)) as f64
+        } else {
+            0.5
+        };
+        let x = ax as f64 + (bx as f64 - ax as f64) * t - 1.0;
+        let y = ay as f64 + (by as f64 - ay as f64) * t - 1.0;
+        (x, y)
+    }
+
+    /// Marching squares: the segments separating cells reachable within
+    /// `level` seconds from the rest.
+    fn segments(&self, level: f32) -> Vec<(Edge, Edge)> {
+        let mut segments = vec![];
+        for y in 0..self.height - 1 {
+            for x in 0..self.width - 1 {
+                // Corners clockwise from the top left, and the edge following each.
+                let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+                let edges = [
+                    Edge::Horizontal(x, y),
+                    Edge::Vertical(x + 1, y),
+                    Edge::Horizontal(x, y + 1),
+                    Edge::Vertical(x, y),
+                ];
+                let inside: Vec<bool> = corners
+                    .iter()
+                    .map(|&(cx, cy)| self.get(cx, cy) <= level)
+                    .collect();
+                let crossed: Vec<Edge> = (0..4)
+                    .filter(|&i| inside[i] != inside[(i + 1) % 4])
+                    .map(|i| edges[i])
+                    .collect();
+
+                match crossed.len() {
+                    2 => segments.push((crossed[0], crossed[1])),
+                    4 => {
+                        // A saddle: cut off the corners that disagree with the
+                        // middle of the cell.
+                        let center =
+                            corners.iter().map(|&(cx, cy)| self.get(cx, cy)).sum::<f32>() / 4.0;
+                        let center_inside = center <= level;
+                        for i in 0..4 {
+                            if inside[i] != center_inside {
+                                segments.push((edges[(i + 3) % 4], edges[i]));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        segments
+    }
+
+    /// Joins the contour segments at `level` into closed rings of pixel coordinates.
+    fn rings(&self, level: f32) -> Vec<Vec<(f64, f64)>> {
+        let mut adjacent: HashMap<Edge, Vec<Edge>> = HashMap::default();
+        for (a, b) in self.segments(level) {
+            adjacent.entry(a).or_default().push(b);
+            adjacent.entry(b).or_default().push(a);
+        }
+
+        let mut rings = vec![];
+        while let Some(&start) = adjacent.keys().next() {
+            let mut ring = vec![];
+            let mut cur = start;
+            while let Some(next) = adjacent.get_mut(&cur).and_then(|n| n.pop()) {
+                ring.push(self.crossing(cur, level));
+                if let Some(back) = adjacent.get_mut(&next) {
+                    if let Some(i) = back.iter().position(|&e| e == cur) {
+                        back.swap_remove(i);
+                    }
+                }
+                if adjacent[&cur].is_empty() {
+                    adjacent.remove(&cur);
+                }
+                cur = next;
+            }
+            adjacent.remove(&cur);
+            if ring.len() >= 3 {
+                rings.push(ring);
+            }
+        }
+        rings
+    }
+
+    /// The area reachable within `level` seconds, as polygons of an outer
+    /// ring followed by its holes.
+    fn polygons(&self, level: f32) -> Vec<Vec<Vec<(f64, f64)>>> {
+        let rings = self.rings(level);
+        let depths: Vec<usize> = rings
+            .iter()
+            .enumerate()
+            .map(|(i, ring)| {
+                (0..rings.len())
+                    .filter(|&j| j != i && contains(&rings[j], ring[0]))
+                    .count()
+            })
+            .collect();
+
+        let mut polygons: Vec<Vec<Vec<(f64, f64)>>> = vec![];
+        let mut outer_index = HashMap::default();
+        for (i, ring) in rings.iter().enumerate() {
+            if depths[i].is_multiple_of(2) {
+                outer_index.insert(i, polygons.len());
+                polygons.push(vec![ring.clone()]);
+            }
+        }
+        for (i, ring) in rings.iter().enumerate() {
+            if depths[i] % 2 == 1 {
+                // A hole belongs to the innermost outer ring around it.
+                let parent = (0..rings.len())
+                    .find(|&j| depths[j] + 1 == depths[i] && contains(&rings[j], ring[0]));
+                if let Some(parent) = parent {
+                    polygons[outer_index[&parent]].push(ring.clone());
+                }
+            }
+        }
+        polygons
+    }
+}
+
+// Ray casting point-in-polygon test.
+fn contains(ring: &[(f64, f64)], (px, py): (f64, f64)) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > py) != (yj > py) && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    let mut area = 0.0;
+    for i in 0..ring.len() {
+        let (ax, ay) = ring[i];
+        let (bx, by) = ring[(i + 1) % ring.len()];
+        area += ax * by - bx * ay;
+    }
+    area / 2.0
+}
+
+#[derive(Serialize)]
+struct FeatureCollection {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    features: Vec<Feature>,
+}
+
+#[derive(Serialize)]
+struct Feature {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    properties: Properties,
+    geometry: Geometry,
+}
+
+#[derive(Serialize)]
+struct Properties {
+    minutes: f32,
+}
+
+#[derive(Serialize)]
+struct Geometry {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    coordinates: Vec<Vec<Vec<[f64; 2]>>>,
+}
+
+/// Writes the isochrones as a GeoJSON `MultiPolygon` per contour level, in lat/lon.
+pub fn write_geojson(
+    path: &Path,
+    settings: &RenderSettings,
+    projection: &Projection,
+    grid: &[Option<TimeCost>],
+) -> Result<(), Box<Error>> {
+    let field = Field::new(settings, grid);
+    let mut features = vec![];
+    for &minutes in &settings.contours {
+        let polygons = field.polygons(minutes * 60.0);
+        let coordinates = polygons
+            .into_iter()
+            .map(|polygon| {
+                polygon
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, ring)| {
+                        let mut ring: Vec<[f64; 2]> = ring
+                            .into_iter()
+                            .map(|(x, y)| {
+                                let (x, y) = settings.pixel_coords(x, y);
+                                let (lat, lon) = projection.to_lat_lon(x, y);
+                                [lon, lat]
+                            })
+                            .collect();
+                        // GeoJSON wants outer rings counterclockwise and holes clockwise.
+                        let points: Vec<(f64, f64)> = ring.iter().map(|p| (p[0], p[1])).collect();
+                        let counterclockwise = signed_area(&points) > 0.0;
+                        if counterclockwise != (i == 0) {
+                            ring.reverse();
+                        }
+                        let first = ring[0];
+                        ring.push(first);
+                        ring
+                    })
+                    .collect()
+            })
+            .collect();
+        features.push(Feature {
+            kind: "Feature",
+            properties: Properties { minutes: minutes },
+            geometry: Geometry {
+                kind: "MultiPolygon",
+                coordinates: coordinates,
+            },
+        });
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    to_writer(
+        file,
+        &FeatureCollection {
+            kind: "FeatureCollection",
+            features: features,
+        },
+    )?;
+    Ok(())
+}
+
+/// Writes the isochrones as an SVG the same size as the rendered image, so it
+/// can be laid on top of it.
+pub fn write_svg(
+    path: &Path,
+    settings: &RenderSettings,
+    grid: &[Option<TimeCost>],
+) -> Result<(), Box<Error>> {
+    let field = Field::new(settings, grid);
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}" "#,
+            r#"viewBox="0 0 {0} {0}">"#
+        ),
+        settings.size
+    )?;
+
+    // Largest first, so the smaller areas are drawn on top.
+    let mut levels = settings.contours.clone();
+    levels.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    for (i, &minutes) in levels.iter().enumerate() {
+        let mut d = String::new();
+        for ring in field.rings(minutes * 60.0) {
+            for (j, &(x, y)) in ring.iter().enumerate() {
+                let command = if j == 0 { 'M' } else { 'L' };
+                d.push_str(&format!("{}{:.2},{:.2} ", command, x + 0.5, y + 0.5));
+            }
+            d.push_str("Z ");
+        }
+        let shade = 255 - (i + 1) * 200 / levels.len();
+        writeln!(
+            out,
+            concat!(
+                r#"  <path data-minutes="{}" d="{}" fill="rgb({},{},255)" fill-opacity="0.4" "#,
+                r#"fill-rule="evenodd" stroke="black" stroke-width="1"/>"#
+            ),
+            minutes,
+            d.trim(),
+            shade,
+            shade
+        )?;
+    }
+    writeln!(out, "</svg>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn field(rows: &[&[f32]]) -> Field {
+    // Padded the same way as `Field::new`.
+    let (width, height) = (rows[0].len() + 2, rows.len() + 2);
+    let mut values = vec![f32::INFINITY; width * height];
+    for (y, row) in rows.iter().enumerate() {
+        for (x, &value) in row.iter().enumerate() {
+            values[(y + 1) * width + (x + 1)] = value;
+        }
+    }
+    Field {
+        width: width,
+        height: height,
+        values: values,
+    }
+}
+
+#[cfg(test)]
+fn assert_closed(ring: &[(f64, f64)]) {
+    // Each point is on an edge of the cell the last one is, all the way round
+    // from the last point back to the first.
+    for i in 0..ring.len() {
+        let ((ax, ay), (bx, by)) = (ring[i], ring[(i + 1) % ring.len()]);
+        assert!((ax - bx).abs() <= 1.0 && (ay - by).abs() <= 1.0, "gap in {:?}", ring);
+        assert!(ring[i] != ring[(i + 1) % ring.len()], "repeated point in {:?}", ring);
+    }
+}
+
+#[test]
+fn rings_around_one_area() {
+    let far = 100.0;
+    let field = field(&[&[far, far, far], &[far, 0.0, far], &[far, far, far]]);
+    let rings = field.rings(50.0);
+    assert_eq!(rings.len(), 1);
+    assert_closed(&rings[0]);
+    assert!(contains(&rings[0], (1.0, 1.0)));
+    assert!(!contains(&rings[0], (0.0, 1.0)));
+    // The unreachable border closes off areas running up to the edge.
+    let rings = field.rings(200.0);
+    assert_eq!(rings.len(), 1);
+    assert_closed(&rings[0]);
+    assert!(contains(&rings[0], (0.0, 0.0)) && contains(&rings[0], (2.0, 2.0)));
+}
+
+#[test]
+fn splits_saddles_by_their_middle() {
+    // Opposite corners are reachable. They're joined across the cell if its
+    // middle is too, and kept apart otherwise.
+    let joined = field(&[&[0.0, 90.0], &[90.0, 0.0]]).rings(50.0);
+    assert_eq!(joined.len(), 1);
+    assert_closed(&joined[0]);
+    assert!(contains(&joined[0], (0.5, 0.5)));
+    let apart = field(&[&[0.0, 100.0], &[100.0, 10.0]]).rings(50.0);
+    assert_eq!(apart.len(), 2);
+    for ring in &apart {
+        assert_closed(ring);
+        assert!(!contains(ring, (0.5, 0.5)));
+    }
+}
+
+#[test]
+fn nests_holes_in_their_areas() {
+    // Reachable everywhere but a band around the middle, so there's an area
+    // with a hole in it, and an island in the hole.
+    let (o, x) = (0.0, 100.0);
+    let field = field(&[
+        &[o, o, o, o, o, o, o],
+        &[o, x, x, x, x, x, o],
+        &[o, x, o, o, o, x, o],
+        &[o, x, o, o, o, x, o],
+        &[o, x, o, o, o, x, o],
+        &[o, x, x, x, x, x, o],
+        &[o, o, o, o, o, o, o],
+    ]);
+    let mut polygons = field.polygons(50.0);
+    polygons.sort_by_key(|polygon| polygon.len());
+    assert_eq!(polygons.len(), 2);
+    for ring in polygons.iter().flatten() {
+        assert_closed(ring);
+    }
+
+    let (island, outer) = (&polygons[0], &polygons[1]);
+    assert_eq!((island.len(), outer.len()), (1, 2));
+    let middle = (3.0, 3.0);
+    assert!(contains(&island[0], middle));
+    assert!(!contains(&island[0], (1.0, 1.0)));
+    // The hole is inside the outer ring, and around the island.
+    assert!(contains(&outer[0], middle) && contains(&outer[1], middle));
+    assert!(contains(&outer[0], outer[1][0]));
+    assert!(!contains(&outer[1], outer[0][0]));
+    assert!(contains(&outer[1], island[0][0]));
+    assert!(contains(&outer[1], (1.0, 3.0)) && !contains(&outer[1], (0.0, 3.0)));
+}
@@ -1,82 +1,383 @@
+use colormap::{self, ColorMap, Ramp, Scale};
+use compare;
+use contour;
+use fnv::FnvHashMap as HashMap;
+use font;
+use gif;
+use num_traits::Zero;
 use png::*;
+use progress::Progress;
+use projection::Projection;
 use rayon::prelude::*;
+use stats;
 use std::error::Error;
 use std::fs::File;
 use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use time::TimeCost;
 
-const RENDER_SIZE: f64 = 15.0;
+// Where the color scale of an animation ends when it isn't given, in minutes.
+// Every frame has to share one scale for them to be comparable.
+const ANIMATION_MAX_MINUTES: f32 = 90.0;
+// How big a change in minutes the ends of a difference map's colors stand
+// for, when it isn't given.
+const DIFFERENCE_MAX_MINUTES: f32 = 30.0;
+// Width in pixels of the cells adaptive rendering starts from. A power of two,
+// so they split evenly down to single pixels.
+const ADAPTIVE_CELL: u32 = 16;
 
-pub fn draw<F>(f: F)
+pub struct RenderSettings {
+    // Width and height of the image in pixels
+    pub size: u32,
+    // Distance in km from the center of the map to its edges
+    pub extent: f64,
+    pub output: PathBuf,
+    pub colors: ColorMap,
+    pub legend: bool,
+    // Isochrones to export, in minutes
+    pub contours: Vec<f32>,
+    pub geojson: Option<PathBuf>,
+    pub svg: Option<PathBuf>,
+    // Where to write reachability statistics, and census blocks to weigh them by
+    pub stats: Option<PathBuf>,
+    pub population: Option<PathBuf>,
+    // Minutes
+    pub stats_bin: f32,
+    // Render adaptively, interpolating wherever travel times differ by less
+    // than this many minutes
+    pub tolerance: Option<f32>,
+}
+
+impl RenderSettings {
+    /// Converts (possibly fractional) pixel coordinates into km around the origin.
+    pub fn pixel_coords(&self, x: f64, y: f64) -> (f64, f64) {
+        let size = self.size as f64;
+        let dest_x = (x / size - 0.5) * (2.0 * self.extent);
+        let dest_y = (y / size - 0.5) * (2.0 * self.extent * -1.0);
+        (dest_x, dest_y)
+    }
+
+    /// The inverse of `pixel_coords`, giving the closest pixel if it's on the map.
+    pub fn pixel_at(&self, x: f64, y: f64) -> Option<(u32, u32)> {
+        let size = self.size as f64;
+        let pixel_x = ((x / (2.0 * self.extent) + 0.5) * size).round();
+        let pixel_y = ((y / (-2.0 * self.extent) + 0.5) * size).round();
+        if pixel_x < 0.0 || pixel_y < 0.0 || pixel_x >= size || pixel_y >= size {
+            return None;
+        }
+        Some((pixel_x as u32, pixel_y as u32))
+    }
+}
+
+pub fn draw<F>(
+    settings: &RenderSettings,
+    projection: &Projection,
+    f: F,
+) -> Result<(), Box<Error>>
 where
     F: Fn(f64, f64) -> Option<TimeCost> + Sync + Send,
 {
-    let width = 1000;
-    let height = 1000;
-    let values = normalize(&render(width, height, f));
-    let mut pixels = Vec::with_capacity(values.len() * 4);
-    for pix in values {
-        if let Some(pix) = pix {
-            pixels.push((pix.walk_time * 255.0) as u8);
-            pixels.push((pix.bus_time * 255.0) as u8);
-            pixels.push((pix.wait_time * 255.0) as u8);
-            pixels.push(255);
-        } else {
-            pixels.push(0);
-            pixels.push(0);
-            pixels.push(0);
-            pixels.push(0);
+    let width = settings.size;
+    let height = settings.size;
+    let grid = render_times(settings, f);
+    if let Some(ref path) = settings.geojson {
+        contour::write_geojson(path, settings, projection, &grid)?;
+    }
+    if let Some(ref path) = settings.svg {
+        contour::write_svg(path, settings, &grid)?;
+    }
+    if let Some(ref path) = settings.stats {
+        let blocks = settings
+            .population
+            .as_ref()
+            .map(|blocks| stats::read_blocks(blocks, settings, projection))
+            .transpose()?;
+        let blocks = blocks.as_ref().map(|b| &b[..]);
+        let report = stats::report(settings, &grid, blocks, settings.stats_bin);
+        report.write(path)?;
+    }
+
+    let mut pixels = settings.colors.paint(&grid);
+    if settings.legend {
+        let range = settings.colors.range(&grid);
+        settings.colors.draw_legend(&mut pixels, width, height, range, "min");
+    }
+    save_image(&settings.output, &pixels, width, height)
+}
+
+/// Like `draw`, but for a score per pixel (e.g. how many places are in reach)
+/// rather than a travel time. `range` is the span of scores the colors cover.
+pub fn draw_scores<F>(
+    settings: &RenderSettings,
+    range: (f32, f32),
+    f: F,
+) -> Result<(), Box<Error>>
+where
+    F: Fn(f64, f64) -> Option<f32> + Sync + Send,
+{
+    let width = settings.size;
+    let height = settings.size;
+    let grid = render(settings, f);
+
+    let mut pixels = settings.colors.paint_values(&grid, range);
+    if settings.legend {
+        settings.colors.draw_legend(&mut pixels, width, height, range, "");
+    }
+    save_image(&settings.output, &pixels, width, height)
+}
+
+/// Draws how travel times changed from `before` to `after`, two grids from
+/// `render` over the same area, from blue where it got faster to red where it
+/// got slower.
+pub fn draw_difference(
+    settings: &RenderSettings,
+    before: &[Option<TimeCost>],
+    after: &[Option<TimeCost>],
+) -> Result<(), Box<Error>> {
+    let width = settings.size;
+    let height = settings.size;
+    let max = settings.colors.max_minutes.unwrap_or(DIFFERENCE_MAX_MINUTES);
+    let range = (-max, max);
+    let colors = ColorMap {
+        ramp: Ramp::Diverging,
+        scale: Scale::Linear,
+        ..settings.colors
+    };
+
+    let mut pixels = colors.paint_values(&compare::difference(before, after, max), range);
+    if settings.legend {
+        colors.draw_legend(&mut pixels, width, height, range, "min");
+    }
+    save_image(&settings.output, &pixels, width, height)
+}
+
+/// Renders frames one at a time into an animated GIF, with a label in the
+/// corner of each.
+pub struct Animation<'a> {
+    settings: &'a RenderSettings,
+    encoder: gif::Encoder<BufWriter<File>>,
+    // How long each frame is shown, in hundredths of a second
+    delay: u16,
+}
+
+impl<'a> Animation<'a> {
+    pub fn new(settings: &'a RenderSettings, delay_ms: u32) -> Result<Self, Box<Error>> {
+        // Scoped, since png's encoder has a `set` too.
+        use gif::SetParameter;
+        let file = BufWriter::new(File::create(&settings.output)?);
+        let size = settings.size as u16;
+        let mut encoder = gif::Encoder::new(file, size, size, &[])?;
+        encoder.set(gif::Repeat::Infinite)?;
+        Ok(Animation {
+            settings: settings,
+            encoder: encoder,
+            delay: (delay_ms / 10) as u16,
+        })
+    }
+
+    pub fn add_frame<F>(&mut self, label: &str, f: F) -> Result<(), Box<Error>>
+    where
+        F: Fn(f64, f64) -> Option<TimeCost> + Sync + Send,
+    {
+        let size = self.settings.size;
+        let colors = &self.settings.colors;
+        let grid = render_times(self.settings, f);
+        let minutes: Vec<Option<f32>> =
+            grid.iter().map(|x| x.map(|x| x.total() / 60.0)).collect();
+        let range = (0.0, colors.max_minutes.unwrap_or(ANIMATION_MAX_MINUTES));
+
+        let mut pixels = colors.paint_values(&minutes, range);
+        if self.settings.legend {
+            colors.draw_legend(&mut pixels, size, size, range, "min");
         }
+        draw_label(&mut pixels, size, label);
+
+        let mut frame = gif::Frame::from_rgba_speed(size as u16, size as u16, &mut pixels, 10);
+        frame.delay = self.delay;
+        self.encoder.write_frame(&frame)?;
+        Ok(())
     }
-    save_image(&pixels, width, height).unwrap();
 }
 
-fn render<F>(width: u32, height: u32, f: F) -> Vec<Option<TimeCost>>
+/// Draws `text` on a white box in the top left of the image.
+fn draw_label(pixels: &mut [u8], width: u32, text: &str) {
+    let scale = (width / 200).max(1);
+    let margin = 4 * scale;
+    let (text_width, text_height) = (font::text_width(text, scale), font::text_height(scale));
+    let white = [255, 255, 255, 255];
+    let size = (text_width + 2 * margin, text_height + 2 * margin);
+    colormap::fill(pixels, width, (margin, margin), size, white);
+    font::draw_text(pixels, width, (2 * margin, 2 * margin), scale, text, [0, 0, 0, 255]);
+}
+
+/// Evaluates `f` at every pixel, in km around the origin, row by row from the
+/// top.
+fn render<T, F>(settings: &RenderSettings, f: F) -> Vec<T>
 where
-    F: Fn(f64, f64) -> Option<TimeCost> + Sync + Send,
+    T: Send,
+    F: Fn(f64, f64) -> T + Sync + Send,
 {
+    let width = settings.size;
+    let height = settings.size;
     let mut result = Vec::with_capacity((height * width) as usize);
+    let progress = Progress::new("Rendering rows", height as usize);
     for y in 0..height {
-        println!("{}", height - y);
         let row = (0..width).collect::<Vec<_>>();
         let row = row.par_iter().map(|&x| {
-            let dest_x = (x as f64 / width as f64 - 0.5) * (2.0 * RENDER_SIZE);
-            let dest_y = (y as f64 / height as f64 - 0.5) * (2.0 * RENDER_SIZE * -1.0);
+            let (dest_x, dest_y) = settings.pixel_coords(x as f64, y as f64);
             f(dest_x, dest_y)
         });
         result.par_extend(row);
+        progress.add(1);
     }
+    progress.finish();
     result
 }
 
-fn normalize(data: &[Option<TimeCost>]) -> Vec<Option<TimeCost>> {
-    // TY: This is log2 vs. linear choice.
-    //for datum in data.iter_mut() {
-    //    *datum = datum.map(|x| x.log2());
-    //}
-    let min = data.iter()
-        .cloned()
-        .filter_map(|x| x)
-        .fold(TimeCost::with_all(1.0 / 0.0), ::std::cmp::min);
-    let max = data.iter()
-        .cloned()
-        .filter_map(|x| x)
-        .fold(TimeCost::with_all(-1.0 / 0.0), ::std::cmp::max);
-
-    data.iter()
-        .map(|x| {
-            x.map(|x| TimeCost {
-                walk_time: (x.walk_time - min.walk_time) / (max.walk_time - min.walk_time),
-                bus_time: (x.bus_time - min.bus_time) / (max.bus_time - min.bus_time),
-                wait_time: (x.wait_time - min.wait_time) / (max.wait_time - min.wait_time),
-                transfers: x.transfers,
+/// Travel times over the map, adaptively if `settings` asks for it.
+pub fn render_times<F>(settings: &RenderSettings, f: F) -> Vec<Option<TimeCost>>
+where
+    F: Fn(f64, f64) -> Option<TimeCost> + Sync + Send,
+{
+    match settings.tolerance {
+        Some(tolerance) => render_adaptive(settings, tolerance, f),
+        None => render(settings, f),
+    }
+}
+
+/// Like `render`, but only evaluates `f` at the corners of cells. Cells whose
+/// corners differ by more than `tolerance` minutes (or are reachable at some
+/// corners but not others) are split in four, and the rest are filled in by
+/// interpolating between their corners.
+fn render_adaptive<F>(settings: &RenderSettings, tolerance: f32, f: F) -> Vec<Option<TimeCost>>
+where
+    F: Fn(f64, f64) -> Option<TimeCost> + Sync + Send,
+{
+    let size = settings.size;
+    let mut grid = vec![None; (size * size) as usize];
+    let starts: Vec<u32> = (0..size).filter(|i| i % ADAPTIVE_CELL == 0).collect();
+    // Neighboring cells share corners, so every cell samples through one map.
+    let samples = Mutex::new(HashMap::default());
+    let progress = Progress::new("Rendering rows of cells", starts.len());
+    for &y in &starts {
+        let cells: Vec<_> = starts
+            .par_iter()
+            .map(|&x| {
+                let mut sampler = Sampler {
+                    settings: settings,
+                    f: &f,
+                    tolerance: tolerance,
+                    samples: &samples,
+                    out: vec![],
+                };
+                sampler.fill(x, y, ADAPTIVE_CELL);
+                sampler.out
             })
-        })
-        .collect()
+            .collect();
+        for (x, y, cost) in cells.into_iter().flatten() {
+            grid[(y * size + x) as usize] = cost;
+        }
+        // Only the bottom edge of this row is shared with the next one.
+        let next = y + ADAPTIVE_CELL;
+        samples.lock().unwrap().retain(|&(_, sample_y), _| sample_y >= next);
+        progress.add(1);
+    }
+    progress.finish();
+    grid
+}
+
+// Fills in one top level cell of an adaptive render.
+struct Sampler<'a, F: 'a> {
+    settings: &'a RenderSettings,
+    f: &'a F,
+    tolerance: f32,
+    // Corners already evaluated by any cell, since neighboring cells share them
+    samples: &'a Mutex<HashMap<(u32, u32), Option<TimeCost>>>,
+    out: Vec<(u32, u32, Option<TimeCost>)>,
+}
+
+impl<'a, F> Sampler<'a, F>
+where
+    F: Fn(f64, f64) -> Option<TimeCost>,
+{
+    fn sample(&mut self, x: u32, y: u32) -> Option<TimeCost> {
+        if let Some(&cost) = self.samples.lock().unwrap().get(&(x, y)) {
+            return cost;
+        }
+        // Not holding the lock while searching, so another cell might sample
+        // the same corner meanwhile, which only wastes a search.
+        let (dest_x, dest_y) = self.settings.pixel_coords(x as f64, y as f64);
+        let cost = (self.f)(dest_x, dest_y);
+        self.samples.lock().unwrap().insert((x, y), cost);
+        cost
+    }
+
+    fn fill(&mut self, x: u32, y: u32, step: u32) {
+        let size = self.settings.size;
+        if x >= size || y >= size {
+            return;
+        }
+        if step == 1 {
+            let cost = self.sample(x, y);
+            self.out.push((x, y, cost));
+            return;
+        }
+
+        let corners = [
+            self.sample(x, y),
+            self.sample(x + step, y),
+            self.sample(x, y + step),
+            self.sample(x + step, y + step),
+        ];
+        let minutes: Vec<f32> = corners.iter().flatten().map(|c| c.total() / 60.0).collect();
+        let smooth = match minutes.len() {
+            0 => true,
+            4 => {
+                let min = minutes.iter().cloned().fold(1.0 / 0.0, f32::min);
+                let max = minutes.iter().cloned().fold(-1.0 / 0.0, f32::max);
+                max - min <= self.tolerance
+            }
+            _ => false,
+        };
+        if !smooth {
+            let half = step / 2;
+            self.fill(x, y, half);
+            self.fill(x + half, y, half);
+            self.fill(x, y + half, half);
+            self.fill(x + half, y + half, half);
+            return;
+        }
+
+        for pixel_y in y..(y + step).min(size) {
+            for pixel_x in x..(x + step).min(size) {
+                let t = ((pixel_x - x) as f32 / step as f32, (pixel_y - y) as f32 / step as f32);
+                self.out.push((pixel_x, pixel_y, blend(&corners, t)));
+            }
+        }
+    }
+}
+
+/// Bilinear interpolation between the corners of a cell, ordered top left, top
+/// right, bottom left, bottom right. `tx` and `ty` go from 0 to 1 across it.
+fn blend(corners: &[Option<TimeCost>; 4], (tx, ty): (f32, f32)) -> Option<TimeCost> {
+    let weights = [(1.0 - tx) * (1.0 - ty), tx * (1.0 - ty), (1.0 - tx) * ty, tx * ty];
+    let mut out = TimeCost::zero();
+    let mut transfers = 0.0;
+    for (corner, &weight) in corners.iter().zip(&weights) {
+        let corner = (*corner)?;
+        out.walk_time += corner.walk_time * weight;
+        out.bus_time += corner.bus_time * weight;
+        out.wait_time += corner.wait_time * weight;
+        out.generalized += corner.generalized * weight;
+        transfers += corner.transfers as f32 * weight;
+    }
+    out.transfers = transfers.round() as u32;
+    Some(out)
 }
 
-fn save_image(data: &[u8], width: u32, height: u32) -> Result<(), Box<Error>> {
-    let file = File::create("./out/out.png")?;
+fn save_image(path: &Path, data: &[u8], width: u32, height: u32) -> Result<(), Box<Error>> {
+    let file = File::create(path)?;
     let w = &mut BufWriter::new(file);
     let mut encoder = Encoder::new(w, width, height);
     encoder.set(ColorType::RGBA).set(BitDepth::Eight);
@@ -0,0 +1,843 @@
+use cache::CacheError;
+use csv;
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+use model::{format_time, parse_time, TripName};
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+// Seconds in a day. Service days run past 24:00:00 rather than wrapping.
+pub const DAY: u64 = 24 * 60 * 60;
+
+/// Why a feed couldn't be loaded, or why some of it was skipped.
+#[derive(Debug)]
+pub enum FeedError {
+    // The feed itself, or a table it has to have
+    MissingFile(String),
+    BadRow {
+        file: String,
+        line: u64,
+        message: String,
+    },
+    BadTime {
+        file: String,
+        line: u64,
+        time: String,
+    },
+    InconsistentTrip {
+        trip_id: String,
+        message: String,
+    },
+    Io(io::Error),
+    Zip(ZipError),
+    Cache(CacheError),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FeedError::MissingFile(ref file) => write!(f, "GTFS feed is missing {}", file),
+            FeedError::BadRow {
+                ref file,
+                line,
+                ref message,
+            } => write!(f, "{} line {}: {}", file, line, message),
+            FeedError::BadTime {
+                ref file,
+                line,
+                ref time,
+            } => write!(f, "{} line {}: '{}' isn't an HH:MM:SS time", file, line, time),
+            FeedError::InconsistentTrip {
+                ref trip_id,
+                ref message,
+            } => write!(f, "trip {}: {}", trip_id, message),
+            FeedError::Io(ref e) => write!(f, "couldn't read the feed: {}", e),
+            FeedError::Zip(ref e) => write!(f, "couldn't read the zipped feed: {}", e),
+            FeedError::Cache(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for FeedError {}
+
+impl From<io::Error> for FeedError {
+    fn from(e: io::Error) -> Self {
+        FeedError::Io(e)
+    }
+}
+
+impl From<ZipError> for FeedError {
+    fn from(e: ZipError) -> Self {
+        FeedError::Zip(e)
+    }
+}
+
+impl From<CacheError> for FeedError {
+    fn from(e: CacheError) -> Self {
+        FeedError::Cache(e)
+    }
+}
+
+/// Numbers for GTFS ids, which can be any string, so the rest of the program
+/// can use them as `StopId`s and trip ids. Numbered from 0 in the order
+/// they're first seen.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ids {
+    numbers: HashMap<String, u32>,
+    names: Vec<String>,
+}
+
+impl Ids {
+    pub fn intern(&mut self, id: &str) -> u32 {
+        if let Some(&number) = self.numbers.get(id) {
+            return number;
+        }
+        let number = self.names.len() as u32;
+        self.numbers.insert(id.to_owned(), number);
+        self.names.push(id.to_owned());
+        number
+    }
+
+    /// A new number for the same id as `number`, for when one trip runs
+    /// twice. Looking the id up still gives the first number.
+    fn renumber(&mut self, number: u32) -> u32 {
+        let id = self.names[number as usize].clone();
+        self.names.push(id);
+        self.names.len() as u32 - 1
+    }
+
+    pub fn get(&self, id: &str) -> Option<u32> {
+        self.numbers.get(id).cloned()
+    }
+
+    /// The id as the feed has it.
+    pub fn name(&self, number: u32) -> &str {
+        &self.names[number as usize]
+    }
+}
+
+impl Index<&str> for Ids {
+    type Output = u32;
+
+    fn index(&self, id: &str) -> &u32 {
+        &self.numbers[id]
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RawStop {
+    // GTFS ids can be any string, e.g. "1108" or "place_CWIS"
+    pub stop_id: String,
+    pub stop_name: String,
+    pub stop_lat: f64,
+    pub stop_lon: f64,
+    #[serde(default)]
+    pub zone_id: Option<String>,
+    #[serde(default)]
+    pub stop_timezone: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RawStopTime {
+    pub trip_id: String,
+    pub stop_id: String,
+    pub arrival_time: String,
+    pub departure_time: String,
+    pub stop_sequence: u32,
+    #[serde(default)]
+    pub stop_headsign: Option<String>,
+    #[serde(default)]
+    pub shape_dist_traveled: Option<f64>,
+}
+
+/// A row of stop_times.txt that's been checked, with its times parsed into
+/// seconds since the start of the service day and its ids numbered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StopTime {
+    pub trip_id: u32,
+    pub stop_id: u32,
+    pub arrival: u64,
+    pub departure: u64,
+    pub stop_sequence: u32,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RawTrip {
+    // GTFS ids can be any string, e.g. "WKDY"
+    pub route_id: String,
+    pub service_id: String,
+    pub trip_id: String,
+    #[serde(default)]
+    pub trip_headsign: Option<String>,
+    #[serde(default)]
+    pub direction_id: Option<u8>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RawRoute {
+    pub route_id: String,
+    #[serde(default)]
+    pub agency_id: Option<String>,
+    #[serde(default)]
+    pub route_short_name: Option<String>,
+    #[serde(default)]
+    pub route_long_name: Option<String>,
+    pub route_type: u32,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RawCalendar {
+    pub service_id: String,
+    pub monday: u8,
+    pub tuesday: u8,
+    pub wednesday: u8,
+    pub thursday: u8,
+    pub friday: u8,
+    pub saturday: u8,
+    pub sunday: u8,
+    // Dates are YYYYMMDD
+    pub start_date: u32,
+    pub end_date: u32,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RawCalendarDate {
+    pub service_id: String,
+    pub date: u32,
+    // 1 = service added on this date, 2 = service removed
+    pub exception_type: u8,
+}
+
+impl RawCalendar {
+    fn runs_on(&self, date: u32) -> bool {
+        if date < self.start_date || date > self.end_date {
+            return false;
+        }
+        let days = [
+            self.monday,
+            self.tuesday,
+            self.wednesday,
+            self.thursday,
+            self.friday,
+            self.saturday,
+            self.sunday,
+        ];
+        days[weekday(date) as usize] == 1
+    }
+}
+
+/// Days since 1970-01-01 for a YYYYMMDD date, from Howard Hinnant's
+/// `days_from_civil`.
+fn days_from_civil(date: u32) -> i64 {
+    let (mut y, m, d) = (date as i64 / 10000, date as i64 / 100 % 100, date as i64 % 100);
+    if m <= 2 {
+        y -= 1;
+    }
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> u32 {
+    let z = days + 719_468;
+    let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y * 10000 + m * 100 + d) as u32
+}
+
+/// Day of the week for a YYYYMMDD date, with Monday as 0.
+pub fn weekday(date: u32) -> u32 {
+    // 1970-01-01 was a Thursday.
+    (days_from_civil(date) + 3).rem_euclid(7) as u32
+}
+
+/// The YYYYMMDD date before `date`.
+pub fn previous_day(date: u32) -> u32 {
+    civil_from_days(days_from_civil(date) - 1)
+}
+
+/// Where the feed's `.txt` tables are read from.
+enum Source {
+    Dir(PathBuf),
+    Zip(ZipArchive<File>),
+}
+
+impl Source {
+    /// Reads every row of `name` along with its line number, or `None` if the
+    /// feed doesn't have that table. Rows that can't be read are left out and
+    /// noted in `skipped`.
+    fn rows<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+        skipped: &mut Vec<FeedError>,
+    ) -> Result<Option<Vec<(u64, T)>>, FeedError> {
+        match self {
+            Source::Dir(dir) => match File::open(dir.join(name)) {
+                Ok(file) => read_table(file, name, skipped).map(Some),
+                Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Source::Zip(archive) => match archive.by_name(name) {
+                Ok(file) => read_table(file, name, skipped).map(Some),
+                Err(ZipError::FileNotFound) => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+        }
+    }
+
+    fn table<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+        skipped: &mut Vec<FeedError>,
+    ) -> Result<Vec<T>, FeedError> {
+        let rows = self.rows(name, skipped)?.unwrap_or_default();
+        Ok(rows.into_iter().map(|(_, row)| row).collect())
+    }
+
+    fn required<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+        skipped: &mut Vec<FeedError>,
+    ) -> Result<Vec<(u64, T)>, FeedError> {
+        match self.rows(name, skipped)? {
+            Some(rows) => Ok(rows),
+            None => Err(FeedError::MissingFile(name.to_owned())),
+        }
+    }
+}
+
+fn read_table<T: DeserializeOwned, R: Read>(
+    reader: R,
+    name: &str,
+    skipped: &mut Vec<FeedError>,
+) -> Result<Vec<(u64, T)>, FeedError> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return Err(bad_row(e, name)),
+    };
+    let mut out = Vec::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => return Ok(out),
+            Err(e) => match *e.kind() {
+                csv::ErrorKind::Io(_) => return Err(bad_row(e, name)),
+                _ => {
+                    skipped.push(bad_row(e, name));
+                    continue;
+                }
+            },
+        }
+        let line = record.position().map_or(0, |p| p.line());
+        match record.deserialize(Some(&headers)) {
+            Ok(row) => out.push((line, row)),
+            Err(e) => skipped.push(bad_row(e, name)),
+        }
+    }
+}
+
+fn bad_row(e: csv::Error, file: &str) -> FeedError {
+    let line = e.position().map_or(0, |p| p.line());
+    let message = match *e.kind() {
+        csv::ErrorKind::Deserialize { ref err, .. } => err.to_string(),
+        csv::ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => format!("expected {} fields but found {}", expected_len, len),
+        _ => e.to_string(),
+    };
+    match e.into_kind() {
+        csv::ErrorKind::Io(e) => FeedError::Io(e),
+        _ => FeedError::BadRow {
+            file: file.to_owned(),
+            line: line,
+            message: message,
+        },
+    }
+}
+
+/// Parses the times of every stop time, leaving out (and noting in `skipped`)
+/// the ones that can't be used: rows with bad times or unknown stops, and
+/// whole trips that go back in time even after `wrap_past_midnight`. Trip ids
+/// are numbered in `trip_ids` as they're seen.
+fn check_stop_times(
+    rows: Vec<(u64, RawStopTime)>,
+    stop_ids: &Ids,
+    trip_ids: &mut Ids,
+    skipped: &mut Vec<FeedError>,
+) -> Vec<StopTime> {
+    let file = "stop_times.txt";
+    let mut trips: HashMap<u32, Vec<StopTime>> = HashMap::default();
+    for (line, raw) in rows {
+        let stop_id = match stop_ids.get(&raw.stop_id) {
+            Some(stop_id) => stop_id,
+            None => {
+                skipped.push(FeedError::BadRow {
+                    file: file.to_owned(),
+                    line: line,
+                    message: format!("stop {} isn't in stops.txt", raw.stop_id),
+                });
+                continue;
+            }
+        };
+        let times = (parse_time(&raw.arrival_time), parse_time(&raw.departure_time));
+        let (arrival, departure) = match times {
+            (Some(arrival), Some(departure)) => (arrival, departure),
+            (None, _) => {
+                skipped.push(FeedError::BadTime {
+                    file: file.to_owned(),
+                    line: line,
+                    time: raw.arrival_time,
+                });
+                continue;
+            }
+            (_, None) => {
+                skipped.push(FeedError::BadTime {
+                    file: file.to_owned(),
+                    line: line,
+                    time: raw.departure_time,
+                });
+                continue;
+            }
+        };
+        let trip_id = trip_ids.intern(&raw.trip_id);
+        trips.entry(trip_id).or_default().push(StopTime {
+            trip_id: trip_id,
+            stop_id: stop_id,
+            arrival: arrival,
+            departure: departure,
+            stop_sequence: raw.stop_sequence,
+        });
+    }
+
+    let mut out = Vec::new();
+    for (trip_id, mut trip) in trips {
+        trip.sort_by_key(|stop| stop.stop_sequence);
+        wrap_past_midnight(&mut trip);
+        let backwards = trip.windows(2).find(|pair| pair[1].arrival < pair[0].departure);
+        if let Some(pair) = backwards {
+            skipped.push(FeedError::InconsistentTrip {
+                trip_id: trip_ids.name(trip_id).to_owned(),
+                message: format!(
+                    "arrives at stop {} at {} before leaving stop {} at {}",
+                    stop_ids.name(pair[1].stop_id),
+                    format_time(pair[1].arrival),
+                    stop_ids.name(pair[0].stop_id),
+                    format_time(pair[0].departure)
+                ),
+            });
+            continue;
+        }
+        out.extend(trip);
+    }
+    out
+}
+
+/// Some feeds restart the clock at midnight instead of going on past 24:00:00.
+/// Wherever a trip's times jump back by more than half a day, this moves them
+/// (and everything after) a day later.
+fn wrap_past_midnight(trip: &mut [StopTime]) {
+    let (mut offset, mut last) = (0, 0);
+    let mut wrap = |time: &mut u64| {
+        if *time + offset + DAY / 2 < last {
+            offset += DAY;
+        }
+        *time += offset;
+        last = *time;
+    };
+    for stop in trip {
+        wrap(&mut stop.arrival);
+        wrap(&mut stop.departure);
+    }
+}
+
+#[derive(Default)]
+pub struct Feed {
+    pub stops: Vec<RawStop>,
+    pub stop_times: Vec<StopTime>,
+    // By their numbers in `trip_ids`
+    pub trips: HashMap<u32, RawTrip>,
+    pub routes: HashMap<String, RawRoute>,
+    pub calendar: Vec<RawCalendar>,
+    pub calendar_dates: Vec<RawCalendarDate>,
+    pub stop_ids: Ids,
+    pub trip_ids: Ids,
+    // Everything left out for being malformed or inconsistent
+    pub skipped: Vec<FeedError>,
+}
+
+impl Feed {
+    /// Loads a feed from either a directory of `.txt` files or a zipped feed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Feed, FeedError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Err(FeedError::MissingFile(path.display().to_string()));
+        }
+        if path.is_dir() {
+            Feed::load(Source::Dir(path.to_owned()))
+        } else {
+            Feed::load(Source::Zip(ZipArchive::new(File::open(path)?)?))
+        }
+    }
+
+    fn load(mut source: Source) -> Result<Feed, FeedError> {
+        let mut skipped = vec![];
+        let stops: Vec<RawStop> = source
+            .required("stops.txt", &mut skipped)?
+            .into_iter()
+            .map(|(_, stop)| stop)
+            .collect();
+        let mut stop_ids = Ids::default();
+        for stop in &stops {
+            stop_ids.intern(&stop.stop_id);
+        }
+        let mut trip_ids = Ids::default();
+        let stop_times = source.required("stop_times.txt", &mut skipped)?;
+        let stop_times = check_stop_times(stop_times, &stop_ids, &mut trip_ids, &mut skipped);
+        let trips: Vec<RawTrip> = source.table("trips.txt", &mut skipped)?;
+        let trips = trips.into_iter().map(|t| (trip_ids.intern(&t.trip_id), t)).collect();
+        let routes: Vec<RawRoute> = source.table("routes.txt", &mut skipped)?;
+        let calendar = source.table("calendar.txt", &mut skipped)?;
+        let calendar_dates = source.table("calendar_dates.txt", &mut skipped)?;
+
+        Ok(Feed {
+            stops: stops,
+            stop_times: stop_times,
+            trips: trips,
+            routes: routes.into_iter().map(|r| (r.route_id.clone(), r)).collect(),
+            calendar: calendar,
+            calendar_dates: calendar_dates,
+            stop_ids: stop_ids,
+            trip_ids: trip_ids,
+            skipped: skipped,
+        })
+    }
+
+    /// The service ids running on a YYYYMMDD date, taking exceptions into account.
+    pub fn services_on(&self, date: u32) -> HashSet<&str> {
+        let mut services: HashSet<&str> = self.calendar
+            .iter()
+            .filter(|c| c.runs_on(date))
+            .map(|c| c.service_id.as_str())
+            .collect();
+        for exception in self.calendar_dates.iter().filter(|c| c.date == date) {
+            match exception.exception_type {
+                1 => {
+                    services.insert(&exception.service_id);
+                }
+                2 => {
+                    services.remove(exception.service_id.as_str());
+                }
+                _ => {}
+            }
+        }
+        services
+    }
+
+    /// The share of the days the calendar covers that each trip runs on, so
+    /// trips from every service can be counted as an average day's. Empty if
+    /// the feed has no calendar.
+    pub fn trip_shares(&self) -> HashMap<u32, f32> {
+        let dates = self.calendar.iter().flat_map(|c| vec![c.start_date, c.end_date]);
+        let dates = dates.chain(self.calendar_dates.iter().map(|c| c.date));
+        let (first, last) = match (dates.clone().min(), dates.max()) {
+            (Some(first), Some(last)) => (days_from_civil(first), days_from_civil(last)),
+            _ => return HashMap::default(),
+        };
+
+        let mut days: HashMap<&str, u32> = HashMap::default();
+        for day in first..last + 1 {
+            for service in self.services_on(civil_from_days(day)) {
+                *days.entry(service).or_insert(0) += 1;
+            }
+        }
+        let total = (last - first + 1) as f32;
+        self.trips
+            .iter()
+            .map(|(&id, trip)| {
+                let running = days.get(trip.service_id.as_str()).cloned().unwrap_or(0);
+                (id, running as f32 / total)
+            })
+            .collect()
+    }
+
+    /// Drops every trip (and its stop times) that doesn't run on `date`.
+    /// Trips from the day before that are still running after midnight are
+    /// kept as well, from where they are at midnight on. They get new trip
+    /// numbers, since the same trip may run on both days, and their times are
+    /// moved a day earlier to fit in with `date`'s. Which trips run when is
+    /// in trips.txt and the calendar, so it's an error if the feed has none.
+    pub fn retain_service_on(&mut self, date: u32) -> Result<(), FeedError> {
+        if self.trips.is_empty() {
+            return Err(FeedError::MissingFile(
+                "trips.txt, which is needed to pick the trips running on a date".to_owned(),
+            ));
+        }
+        if self.calendar.is_empty() && self.calendar_dates.is_empty() {
+            return Err(FeedError::MissingFile(
+                "calendar.txt and calendar_dates.txt, one of which is needed to pick the trips \
+                 running on a date"
+                    .to_owned(),
+            ));
+        }
+        let mut ran_yesterday: Vec<u32> = {
+            let yesterday = self.services_on(previous_day(date));
+            self.trips
+                .iter()
+                .filter(|&(_, trip)| yesterday.contains(trip.service_id.as_str()))
+                .map(|(&id, _)| id)
+                .collect()
+        };
+        ran_yesterday.sort();
+        let mut owl_trips: HashMap<u32, RawTrip> = HashMap::default();
+        let mut renamed: HashMap<u32, u32> = HashMap::default();
+        for id in ran_yesterday {
+            let owl_id = self.trip_ids.renumber(id);
+            owl_trips.insert(owl_id, self.trips[&id].clone());
+            renamed.insert(id, owl_id);
+        }
+        let owl_times: Vec<StopTime> = self
+            .stop_times
+            .iter()
+            .filter(|s| s.arrival >= DAY)
+            .filter_map(|s| {
+                Some(StopTime {
+                    trip_id: *renamed.get(&s.trip_id)?,
+                    arrival: s.arrival - DAY,
+                    departure: s.departure - DAY,
+                    ..s.clone()
+                })
+            })
+            .collect();
+
+        let services: HashSet<String> =
+            self.services_on(date).into_iter().map(|s| s.to_owned()).collect();
+        self.trips.retain(|_, trip| services.contains(&trip.service_id));
+        let trips = &self.trips;
+        self.stop_times.retain(|s| trips.contains_key(&s.trip_id));
+
+        let running: HashSet<u32> = owl_times.iter().map(|s| s.trip_id).collect();
+        owl_trips.retain(|id, _| running.contains(id));
+        self.trips.extend(owl_trips);
+        self.stop_times.extend(owl_times);
+        Ok(())
+    }
+
+    /// The name riders know a trip's route by, e.g. "44" or "RapidRide D Line".
+    pub fn route_name(&self, trip_id: u32) -> Option<&str> {
+        let trip = self.trips.get(&trip_id)?;
+        let route = self.routes.get(&trip.route_id)?;
+        route
+            .route_short_name
+            .as_ref()
+            .or(route.route_long_name.as_ref())
+            .map(|name| name.as_str())
+    }
+
+    /// What riders know each trip by, by trip number.
+    pub fn trip_names(&self) -> HashMap<u32, TripName> {
+        let numbered = self.trip_ids.names.iter().enumerate();
+        numbered
+            .map(|(trip, trip_id)| {
+                let trip = trip as u32;
+                let name = TripName {
+                    trip_id: trip_id.clone(),
+                    route: self.route_name(trip).map(|name| name.to_owned()),
+                };
+                (trip, name)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn wraps_trips_past_midnight() {
+    let mut stop_ids = Ids::default();
+    for id in 1..4 {
+        stop_ids.intern(&id.to_string());
+    }
+    let row = |stop_id: u32, time: &str| {
+        let raw = RawStopTime {
+            trip_id: "1".to_owned(),
+            stop_id: stop_id.to_string(),
+            arrival_time: time.to_owned(),
+            departure_time: time.to_owned(),
+            stop_sequence: stop_id,
+            stop_headsign: None,
+            shape_dist_traveled: None,
+        };
+        (stop_id as u64 + 1, raw)
+    };
+    let rows = vec![row(1, "23:50:00"), row(2, "00:05:00"), row(3, "01:10:00")];
+
+    let mut skipped = vec![];
+    let times = check_stop_times(rows, &stop_ids, &mut Ids::default(), &mut skipped);
+    assert!(skipped.is_empty());
+    let arrivals: Vec<u64> = times.iter().map(|s| s.arrival).collect();
+    assert_eq!(arrivals, vec![23 * 3600 + 50 * 60, DAY + 5 * 60, DAY + 70 * 60]);
+}
+
+#[test]
+fn keeps_owl_service_from_the_day_before() {
+    assert_eq!(previous_day(20240301), 20240229);
+
+    let mut trip_ids = Ids::default();
+    let trip_id = trip_ids.intern("FRI_1");
+    let stop = |stop_id, time| StopTime {
+        trip_id: trip_id,
+        stop_id: stop_id,
+        arrival: time,
+        departure: time,
+        stop_sequence: stop_id,
+    };
+    let trip = RawTrip {
+        route_id: "44".to_owned(),
+        service_id: "FRI".to_owned(),
+        trip_id: "FRI_1".to_owned(),
+        trip_headsign: None,
+        direction_id: None,
+    };
+    // Runs on Fridays, and 2024-01-06 is a Saturday.
+    let fridays = RawCalendar {
+        service_id: "FRI".to_owned(),
+        monday: 0,
+        tuesday: 0,
+        wednesday: 0,
+        thursday: 0,
+        friday: 1,
+        saturday: 0,
+        sunday: 0,
+        start_date: 20240101,
+        end_date: 20241231,
+    };
+    let mut feed = Feed {
+        stops: vec![],
+        stop_times: vec![stop(1, DAY - 600), stop(2, DAY + 300), stop(3, DAY + 4200)],
+        trips: vec![(trip_id, trip)].into_iter().collect(),
+        routes: HashMap::default(),
+        calendar: vec![fridays],
+        calendar_dates: vec![],
+        stop_ids: Ids::default(),
+        trip_ids: trip_ids,
+        skipped: vec![],
+    };
+    let mut without_trips = Feed {
+        trips: HashMap::default(),
+        stop_times: feed.stop_times.clone(),
+        calendar: feed.calendar.clone(),
+        ..Feed::default()
+    };
+    feed.retain_service_on(20240106).unwrap();
+
+    let owl_id = trip_id + 1;
+    assert_eq!(feed.trips.keys().collect::<Vec<_>>(), vec![&owl_id]);
+    assert_eq!(feed.trip_ids.name(owl_id), "FRI_1");
+    let times: Vec<(u32, u64)> = feed.stop_times.iter().map(|s| (s.trip_id, s.arrival)).collect();
+    assert_eq!(times, vec![(owl_id, 300), (owl_id, 4200)]);
+
+    // Without trips.txt there's no telling what runs when.
+    match without_trips.retain_service_on(20240106) {
+        Err(FeedError::MissingFile(_)) => {}
+        other => panic!("expected a missing trips.txt, got {:?}", other),
+    }
+    assert_eq!(without_trips.stop_times.len(), 3);
+}
+
+#[test]
+fn reads_text_ids() {
+    let trips = "route_id,service_id,trip_id\nE Line,WKDY,E_0630\n";
+    let stops = "stop_id,stop_name,stop_lat,stop_lon\n\
+                 place_CWIS,Westlake,47.61,-122.34\n\
+                 1108,3rd & Pike,47.61,-122.34\n";
+    let stop_times = "trip_id,stop_id,arrival_time,departure_time,stop_sequence\n\
+                      E_0630,place_CWIS,06:30:00,06:30:00,1\n\
+                      E_0630,1108,06:35:00,06:35:00,2\n\
+                      E_0630,nowhere,06:40:00,06:40:00,3\n";
+    let mut skipped = vec![];
+    let trips: Vec<(u64, RawTrip)> =
+        read_table(trips.as_bytes(), "trips.txt", &mut skipped).unwrap();
+    let stops: Vec<(u64, RawStop)> =
+        read_table(stops.as_bytes(), "stops.txt", &mut skipped).unwrap();
+    let rows = read_table(stop_times.as_bytes(), "stop_times.txt", &mut skipped).unwrap();
+    assert!(skipped.is_empty());
+    assert_eq!(trips[0].1.route_id, "E Line");
+    assert_eq!(trips[0].1.service_id, "WKDY");
+
+    let mut stop_ids = Ids::default();
+    for (_, stop) in &stops {
+        stop_ids.intern(&stop.stop_id);
+    }
+    let mut trip_ids = Ids::default();
+    let times = check_stop_times(rows, &stop_ids, &mut trip_ids, &mut skipped);
+    // The stop that isn't in stops.txt is left out.
+    assert_eq!(skipped.len(), 1);
+    let numbered: Vec<(u32, u32)> = times.iter().map(|s| (s.trip_id, s.stop_id)).collect();
+    assert_eq!(numbered, vec![(0, 0), (0, 1)]);
+    assert_eq!(trip_ids.get("E_0630"), Some(0));
+    assert_eq!((stop_ids.name(0), stop_ids.name(1)), ("place_CWIS", "1108"));
+}
+
+#[test]
+fn shares_trips_by_days_running() {
+    let week = |service_id: &str, days: [u8; 7]| RawCalendar {
+        service_id: service_id.to_owned(),
+        monday: days[0],
+        tuesday: days[1],
+        wednesday: days[2],
+        thursday: days[3],
+        friday: days[4],
+        saturday: days[5],
+        sunday: days[6],
+        // A Monday to a Sunday
+        start_date: 20240101,
+        end_date: 20240107,
+    };
+    let trip = |trip_id: &str, service_id: &str| RawTrip {
+        route_id: "44".to_owned(),
+        service_id: service_id.to_owned(),
+        trip_id: trip_id.to_owned(),
+        trip_headsign: None,
+        direction_id: None,
+    };
+    let feed = Feed {
+        stops: vec![],
+        stop_times: vec![],
+        trips: vec![(1, trip("1", "WKDY")), (2, trip("2", "SUN"))].into_iter().collect(),
+        routes: HashMap::default(),
+        calendar: vec![week("WKDY", [1, 1, 1, 1, 1, 0, 0]), week("SUN", [0, 0, 0, 0, 0, 0, 1])],
+        // New Year's Day runs on a Sunday schedule.
+        calendar_dates: vec![
+            RawCalendarDate {
+                service_id: "WKDY".to_owned(),
+                date: 20240101,
+                exception_type: 2,
+            },
+            RawCalendarDate {
+                service_id: "SUN".to_owned(),
+                date: 20240101,
+                exception_type: 1,
+            },
+        ],
+        ..Feed::default()
+    };
+
+    let shares = feed.trip_shares();
+    assert_eq!(shares[&1], 4.0 / 7.0);
+    assert_eq!(shares[&2], 2.0 / 7.0);
+}
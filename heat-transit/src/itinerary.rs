@@ -0,0 +1,226 @@
+use model::{Stops, TripNames};
+use pathing::{walking_time, HowGet, Position, TransitSearchProblem};
+use projection::Projection;
+use schedule::Hop;
+use serde_json::to_writer_pretty;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use time::TimeCost;
+
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Place {
+    // Only set for stops
+    pub name: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Clone, Serialize, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Leg {
+    Walk {
+        from: Place,
+        to: Place,
+        // Seconds
+        duration: f32,
+    },
+    Bus {
+        from: Place,
+        to: Place,
+        // As the feed has it
+        trip_id: String,
+        route: Option<String>,
+        // Seconds spent waiting at `from`, then riding to `to`
+        wait: f32,
+        duration: f32,
+    },
+}
+
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Itinerary {
+    pub legs: Vec<Leg>,
+    pub cost: TimeCost,
+}
+
+fn place(stops: &Stops, projection: &Projection, position: &Position) -> Place {
+    let (x, y) = position.get_coords(stops);
+    let (lat, lon) = projection.to_lat_lon(x, y);
+    Place {
+        name: match *position {
+            Position::BusStop(id, _, _) => Some(stops[&id].name.clone()),
+            _ => None,
+        },
+        lat: lat,
+        lon: lon,
+    }
+}
+
+fn trip_name(trip_names: &TripNames, trip_id: u32) -> (String, Option<String>) {
+    match trip_names.get(&trip_id) {
+        Some(name) => (name.trip_id.clone(), name.route.clone()),
+        None => (trip_id.to_string(), None),
+    }
+}
+
+// Walking past a stop without boarding anything is still one walk.
+fn add_walk(legs: &mut Vec<Leg>, from: Place, to: Place, walk_time: f32) {
+    if let Some(&mut Leg::Walk {
+        to: ref mut last_to,
+        ref mut duration,
+        ..
+    }) = legs.last_mut()
+    {
+        *last_to = to;
+        *duration += walk_time;
+        return;
+    }
+    legs.push(Leg::Walk {
+        from: from,
+        to: to,
+        duration: walk_time,
+    });
+}
+
+impl Itinerary {
+    /// Turns the nodes `astar` visited, from the start to the end, back into
+    /// the walks and rides between them.
+    pub fn from_path(
+        searcher: &TransitSearchProblem,
+        projection: &Projection,
+        trip_names: &TripNames,
+        path: &[Position],
+        cost: TimeCost,
+    ) -> Self {
+        let stops = searcher.stops;
+        let mut legs = vec![];
+        for pair in path.windows(2) {
+            let (from, to) = (&pair[0], &pair[1]);
+            match (*from, *to) {
+                (Position::BusStop(start, _, _), Position::BusStop(end, HowGet::Bus, _)) => {
+                    let info = searcher
+                        .connections
+                        .between(start, end)
+                        .expect("the search only rides the connections it was given");
+                    let (trip_id, route) = trip_name(trip_names, info.trip_id);
+                    legs.push(Leg::Bus {
+                        from: place(stops, projection, from),
+                        to: place(stops, projection, to),
+                        trip_id: trip_id,
+                        route: route,
+                        wait: searcher.cost_model.wait.expected_wait(info.headway),
+                        duration: info.time,
+                    });
+                }
+                _ => {
+                    let walk = searcher.walk(from, to).unwrap_or_else(|| {
+                        walking_time(from.get_coords(stops), to.get_coords(stops))
+                    });
+                    let (from, to) = (place(stops, projection, from), place(stops, projection, to));
+                    add_walk(&mut legs, from, to, walk.walk_time);
+                }
+            }
+        }
+        Itinerary {
+            legs: legs,
+            cost: cost,
+        }
+    }
+
+    /// Turns a journey from `ScheduleSearch::journey`, leaving at `departure`
+    /// seconds into the service day, into legs. Waits are however long there
+    /// is between getting to a stop and the bus leaving it.
+    pub fn from_journey(
+        stops: &Stops,
+        projection: &Projection,
+        trip_names: &TripNames,
+        departure: u64,
+        hops: &[Hop],
+        cost: TimeCost,
+    ) -> Self {
+        let mut legs = vec![];
+        // Seconds into the service day
+        let mut clock = departure as f32;
+        for hop in hops {
+            match *hop {
+                Hop::Walk(ref from, ref to) => {
+                    let walk = walking_time(from.get_coords(stops), to.get_coords(stops));
+                    let (from, to) = (place(stops, projection, from), place(stops, projection, to));
+                    add_walk(&mut legs, from, to, walk.walk_time);
+                    clock += walk.walk_time;
+                }
+                Hop::Ride(boarded, got_off) => {
+                    let stop = |id| Position::BusStop(id, HowGet::Bus, 0);
+                    let (trip_id, route) = trip_name(trip_names, boarded.trip_id);
+                    legs.push(Leg::Bus {
+                        from: place(stops, projection, &stop(boarded.from)),
+                        to: place(stops, projection, &stop(got_off.to)),
+                        trip_id: trip_id,
+                        route: route,
+                        wait: (boarded.departure as f32 - clock).max(0.0),
+                        duration: (got_off.arrival - boarded.departure) as f32,
+                    });
+                    clock = got_off.arrival as f32;
+                }
+            }
+        }
+        Itinerary {
+            legs: legs,
+            cost: cost,
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), Box<Error>> {
+        to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "{}", name),
+            None => write!(f, "{:.5},{:.5}", self.lat, self.lon),
+        }
+    }
+}
+
+impl fmt::Display for Itinerary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for leg in &self.legs {
+            match *leg {
+                Leg::Walk {
+                    ref from,
+                    ref to,
+                    duration,
+                } => writeln!(f, "Walk {:.1} min from {} to {}", duration / 60.0, from, to)?,
+                Leg::Bus {
+                    ref from,
+                    ref to,
+                    ref trip_id,
+                    ref route,
+                    wait,
+                    duration,
+                } => {
+                    let route = match *route {
+                        Some(ref route) => format!("route {}", route),
+                        None => "a bus".to_owned(),
+                    };
+                    writeln!(
+                        f,
+                        "Wait {:.1} min at {}, then ride {} (trip {}) for {:.1} min to {}",
+                        wait / 60.0,
+                        from,
+                        route,
+                        trip_id,
+                        duration / 60.0,
+                        to
+                    )?
+                }
+            }
+        }
+        write!(f, "Total: {}", self.cost)
+    }
+}
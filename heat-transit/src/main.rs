@@ -1,38 +1,408 @@
 extern crate astar;
+extern crate bincode;
+extern crate clap;
 extern crate csv;
 extern crate fnv;
+extern crate gif;
 extern crate num_traits;
 extern crate png;
 extern crate rayon;
+extern crate serde;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
+extern crate xml;
+extern crate zip;
 
+mod access;
+mod cache;
+mod cli;
+mod colormap;
+mod compare;
+mod contour;
 mod draw;
+mod font;
+mod gtfs;
+mod itinerary;
 mod model;
+mod pareto;
 mod pathing;
 mod precache;
+mod progress;
+mod projection;
+mod schedule;
+mod spatial;
+mod stats;
+mod streets;
 mod time;
+mod verify;
 
+use cli::Command;
+use draw::RenderSettings;
+use gtfs::FeedError;
+use itinerary::Itinerary;
+use model::FeedOptions;
 use pathing::*;
+use projection::Projection;
+use spatial::StopIndex;
+use std::error::Error;
+use std::process;
+use streets::{StreetWalks, Streets};
+use time::{CostModel, TimeCost};
+
+/// Where a latitude and longitude given on the command line ends up.
+fn locate(projection: &Projection, (lat, lon): (f64, f64)) -> Position {
+    let (x, y) = projection.to_x_y(lat, lon);
+    Position::Custom(x, y)
+}
+
+/// Travel times to `destination` over the map, searching backwards from it.
+/// The map is laid out in `projection`, which can be another feed's.
+fn render_reverse(
+    settings: &RenderSettings,
+    feed: &FeedOptions,
+    projection: &Projection,
+    destination: (f64, f64),
+    cost_model: &CostModel,
+) -> Result<Vec<Option<TimeCost>>, FeedError> {
+    let (stops, connections, feed_projection) = model::get_connections(feed, cost_model.window)?;
+    let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+    let end = locate(&feed_projection, destination).get_coords(&stops);
+    let tree = pathing::search_to(&stops, &index, &connections, cost_model, end);
+    Ok(draw::render_times(settings, |x, y| {
+        let (lat, lon) = projection.to_lat_lon(x, y);
+        let start = feed_projection.to_x_y(lat, lon);
+        Some(best_to_tree(&index, cost_model, &tree, start, end))
+    }))
+}
+
+fn run() -> Result<(), Box<Error>> {
+    let options = cli::parse();
+
+    match options.command {
+        Command::BuildCache {
+            destination,
+            cost_model,
+            streets,
+        } => {
+            let (stops, connections, projection) =
+                model::rebuild_connections(&options.feed, cost_model.window)?;
+            let destination = locate(&projection, destination);
+            let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+            model::rebuild_timetable(&options.feed)?;
+            let streets = streets.map(|path| Streets::load(&path, &projection)).transpose()?;
+            let walks = streets
+                .as_ref()
+                .map(|streets| StreetWalks::new(streets, &stops, destination.get_coords(&stops)));
+            precache::rebuild_cache(
+                &options.feed,
+                &stops,
+                &index,
+                &connections,
+                destination,
+                &cost_model,
+                walks.as_ref(),
+            )?;
+        }
+        Command::Render {
+            destination,
+            departure,
+            reverse,
+            pareto,
+            cost_model,
+            streets,
+            settings,
+        } => {
+            let (stops, connections, projection) =
+                model::get_connections(&options.feed, cost_model.window)?;
+            let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+            let destination = locate(&projection, destination);
+
+            if let Some(departure) = departure {
+                let timetable = model::get_timetable(&options.feed)?;
+                let searcher = schedule::ScheduleSearch::new(&stops, &index, &timetable);
+                draw::draw(&settings, &projection, |x, y| {
+                    let start = Position::Custom(x, y);
+                    Some(searcher.earliest_arrival(start, departure, destination))
+                })?;
+                return Ok(());
+            }
+
+            if reverse {
+                let end = destination.get_coords(&stops);
+                let tree =
+                    pathing::search_to(&stops, &index, &connections, &cost_model, end);
+                draw::draw(&settings, &projection, |x, y| {
+                    Some(best_to_tree(&index, &cost_model, &tree, (x, y), end))
+                })?;
+                return Ok(());
+            }
+
+            if pareto {
+                let end = destination.get_coords(&stops);
+                let bags = pareto::search_to(&stops, &index, &connections, end, &cost_model);
+                let fastest = draw::render_times(&settings, |x, y| {
+                    pareto::options(&index, &bags, (x, y), end).first().cloned()
+                });
+                let fewest = draw::render_times(&settings, |x, y| {
+                    pareto::fewest_transfers(&pareto::options(&index, &bags, (x, y), end))
+                });
+                draw::draw_difference(&settings, &fastest, &fewest)?;
+                return Ok(());
+            }
+
+            let streets = streets.map(|path| Streets::load(&path, &projection)).transpose()?;
+            let walks = streets
+                .as_ref()
+                .map(|streets| StreetWalks::new(streets, &stops, destination.get_coords(&stops)));
+            let precache = precache::get_cache(
+                &options.feed,
+                &stops,
+                &index,
+                &connections,
+                destination,
+                &cost_model,
+                walks.as_ref(),
+            )?;
+            let searcher = TransitSearchProblem {
+                stops: &stops,
+                connections: &connections,
+                index: &index,
+                end: destination,
+                precache: precache,
+                cost_model: cost_model,
+                walks: walks.as_ref(),
+            };
+
+            draw::draw(&settings, &projection, |x, y| {
+                let start = Position::Custom(x, y);
+                astar::astar(&searcher, start).map(|(_, b)| b)
+            })?;
+        }
+        Command::Route {
+            origin,
+            destination,
+            departure,
+            json,
+            pareto,
+            cost_model,
+            streets,
+        } => {
+            let (stops, connections, projection) =
+                model::get_connections(&options.feed, cost_model.window)?;
+            let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+            let origin = locate(&projection, origin);
+            let destination = locate(&projection, destination);
+
+            if let Some(departure) = departure {
+                let timetable = model::get_timetable(&options.feed)?;
+                let searcher = schedule::ScheduleSearch::new(&stops, &index, &timetable);
+                let (hops, cost) = searcher.journey(origin, departure, destination);
+                let trip_names = model::get_trip_names(&options.feed)?;
+                let itinerary = Itinerary::from_journey(
+                    &stops,
+                    &projection,
+                    &trip_names,
+                    departure,
+                    &hops,
+                    cost,
+                );
+                println!("{}", itinerary);
+                if let Some(json) = json {
+                    itinerary.write_json(&json)?;
+                }
+                return Ok(());
+            }
+
+            if pareto {
+                let (start, end) = (origin.get_coords(&stops), destination.get_coords(&stops));
+                let bags = pareto::search_to(&stops, &index, &connections, end, &cost_model);
+                for option in pareto::options(&index, &bags, start, end) {
+                    println!("{}", option);
+                }
+                return Ok(());
+            }
+
+            let streets = streets.map(|path| Streets::load(&path, &projection)).transpose()?;
+            let walks = streets
+                .as_ref()
+                .map(|streets| StreetWalks::new(streets, &stops, destination.get_coords(&stops)));
+            let precache = precache::get_cache(
+                &options.feed,
+                &stops,
+                &index,
+                &connections,
+                destination,
+                &cost_model,
+                walks.as_ref(),
+            )?;
+            let searcher = TransitSearchProblem {
+                stops: &stops,
+                connections: &connections,
+                index: &index,
+                end: destination,
+                precache: precache,
+                cost_model: cost_model,
+                walks: walks.as_ref(),
+            };
+            let (mut path, cost) = match astar::astar(&searcher, origin) {
+                Some(found) => found,
+                None => {
+                    println!("No route found");
+                    return Ok(());
+                }
+            };
+            if path.front() != Some(&origin) {
+                path.push_front(origin);
+            }
+            let path: Vec<Position> = path.into_iter().collect();
+
+            let trip_names = model::get_trip_names(&options.feed)?;
+            let itinerary = Itinerary::from_path(&searcher, &projection, &trip_names, &path, cost);
+            println!("{}", itinerary);
+            if let Some(json) = json {
+                itinerary.write_json(&json)?;
+            }
+        }
+        Command::Compare {
+            destination,
+            against_data,
+            against_destination,
+            threshold,
+            cost_model,
+            settings,
+        } => {
+            let feed = &options.feed;
+            let (_, _, projection) = model::get_connections(feed, None)?;
+            let before = render_reverse(&settings, feed, &projection, destination, &cost_model)?;
+
+            // Kept apart, so the two feeds' caches don't keep replacing each other
+            let against_feed = against_data.map(|data| {
+                FeedOptions::new(
+                    data,
+                    feed.cache.join("against"),
+                    feed.service_date,
+                    feed.projection,
+                    feed.center,
+                )
+            });
+            let after = render_reverse(
+                &settings,
+                against_feed.as_ref().unwrap_or(feed),
+                &projection,
+                against_destination.unwrap_or(destination),
+                &cost_model,
+            )?;
+
+            draw::draw_difference(&settings, &before, &after)?;
+            println!("{}", compare::summarize(&settings, &before, &after, threshold));
+        }
+        Command::Animate {
+            destination,
+            start,
+            end,
+            every,
+            window,
+            delay,
+            cost_model,
+            settings,
+        } => {
+            let (stops, _, projection) = model::get_connections(&options.feed, None)?;
+            let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+            let target = locate(&projection, destination).get_coords(&stops);
+            let timetable = model::get_timetable(&options.feed)?;
+
+            let mut animation = draw::Animation::new(&settings, delay)?;
+            let mut time = start;
+            while time <= end {
+                println!("Frame at {}", model::format_time(time));
+                let connections = timetable.connections_between(time, time + window);
+                let tree = pathing::search_to(&stops, &index, &connections, &cost_model, target);
+                animation
+                    .add_frame(&model::format_time(time), |x, y| {
+                        Some(best_to_tree(&index, &cost_model, &tree, (x, y), target))
+                    })?;
+                time += every;
+            }
+        }
+        Command::Accessibility {
+            destinations,
+            within,
+            cost_model,
+            settings,
+        } => {
+            let (stops, connections, projection) =
+                model::get_connections(&options.feed, cost_model.window)?;
+            let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+            let destinations = access::read_destinations(&destinations, &projection)?;
+            let range = (0.0, destinations.len() as f32);
+            draw::draw_scores(&settings, range, |x, y| {
+                let reach = access::reachable(
+                    &stops,
+                    &index,
+                    &connections,
+                    &cost_model,
+                    (x, y),
+                    &destinations,
+                    within * 60.0,
+                );
+                Some(reach as f32)
+            })?;
+        }
+        Command::Validate => {
+            let skipped = model::validate(&options.feed)?;
+            for problem in &skipped {
+                println!("{}", problem);
+            }
+            println!("{} problems found", skipped.len());
+        }
+        Command::CheckHeuristic {
+            destination,
+            samples,
+            cost_model,
+            streets,
+        } => {
+            let (stops, connections, projection) =
+                model::get_connections(&options.feed, cost_model.window)?;
+            let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+            let destination = locate(&projection, destination);
+
+            let streets = streets.map(|path| Streets::load(&path, &projection)).transpose()?;
+            let walks = streets
+                .as_ref()
+                .map(|streets| StreetWalks::new(streets, &stops, destination.get_coords(&stops)));
+            let precache = precache::get_cache(
+                &options.feed,
+                &stops,
+                &index,
+                &connections,
+                destination,
+                &cost_model,
+                walks.as_ref(),
+            )?;
+            let searcher = TransitSearchProblem {
+                stops: &stops,
+                connections: &connections,
+                index: &index,
+                end: destination,
+                precache: precache,
+                cost_model: cost_model,
+                walks: walks.as_ref(),
+            };
+
+            let report = verify::check(&searcher, &verify::sample_origins(&stops, samples));
+            println!("{}", report);
+            if !report.is_ok() {
+                return Err("the heuristic can't be trusted for this destination".into());
+            }
+        }
+    }
+    Ok(())
+}
 
 fn main() {
-    let (stops, connections) = model::get_connections();
-    let cap_hill = Position::LatLon(47.616760, -122.318097);
-    let _uw = Position::LatLon(47.656016, -122.312520);
-    let ballard = Position::LatLon(47.668809, -122.382799);
-
-    let precache = precache::get_cache(&stops, &connections, ballard);
-
-    let searcher = TransitSearchProblem {
-        stops: &stops,
-        connections: &connections,
-        end: cap_hill, // Cap Hill
-        precache: precache,
-    };
-
-    draw::draw(|x, y| {
-        let start = Position::Custom(x, y);
-        astar::astar(&searcher, start).map(|(_, b)| b)
-    });
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
@@ -1,37 +1,15 @@
-use csv;
+use cache;
 use fnv::FnvHashMap as HashMap;
-use serde_json::{from_reader, to_writer_pretty};
-use std::error::Error;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-
-const ORIGIN_LAT: f64 = 47.6;
-const ORIGIN_LON: f64 = -122.33;
+use gtfs::{Feed, FeedError, RawStop, StopTime};
+use projection::{Projection, ProjectionKind};
+use schedule::{TimedConnection, Timetable};
+use std::cmp::Ordering;
+use std::io::ErrorKind;
+use std::path::PathBuf;
 
 #[derive(Clone, Deserialize, Serialize, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct StopId(pub u32);
 
-#[derive(Deserialize, Clone, Debug, PartialEq)]
-struct RawStop {
-    stop_id: u32,
-    stop_name: String,
-    stop_lat: f64,
-    stop_lon: f64,
-    zone_id: u32,
-    stop_timezone: String,
-}
-
-#[derive(Deserialize, Clone, Debug, PartialEq)]
-struct RawStopTime {
-    trip_id: u32,
-    stop_id: u32,
-    arrival_time: String,
-    departure_time: String,
-    stop_sequence: u32,
-    stop_headsign: String,
-    shape_dist_traveled: f64,
-}
-
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Stop {
     pub stop_id: StopId,
@@ -41,169 +19,581 @@ pub struct Stop {
     pub name: String,
 }
 
-type Connections = HashMap<(StopId, StopId), Connection>;
-pub type PreConnections = HashMap<StopId, HashMap<StopId, Connection>>;
 pub type Stops = HashMap<StopId, Stop>;
+/// What riders know a trip by: its id in the feed, and its route's name if the
+/// feed has one.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TripName {
+    pub trip_id: String,
+    pub route: Option<String>,
+}
+
+// By the number `gtfs::Ids` gives each trip_id
+pub type TripNames = HashMap<u32, TripName>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 struct RouteId(u32);
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Connection {
     pub time: f32,
-    trip_id: u32,
+    pub trip_id: u32,
+    // Average seconds between departures from the first stop to the second
+    pub headway: f32,
+}
+
+/// Trips that make the same stops in the same order, ridden as one route.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Pattern {
+    pub stops: Vec<StopId>,
+    // Seconds from the first stop to each stop on the fastest of the trips
+    pub times: Vec<f32>,
+    // That fastest trip, to tell riders which route this is
+    pub trip_id: u32,
+    // When each trip leaves the first stop, in order
+    pub departures: Vec<u64>,
+    // How many trips have left by each of `departures`, each counted by the
+    // share of days it runs on
+    counted: Vec<f32>,
 }
 
-fn translate_connections(c: Connections) -> PreConnections {
-    let mut out = HashMap::default();
-    for ((start, stop), info) in c {
-        if !out.contains_key(&start) {
-            out.insert(start, HashMap::default());
+impl Pattern {
+    /// How many trips leave the stop at `position` from `from` up to `until`
+    /// seconds into the day, counted the same way as `counted`.
+    fn trips_between(&self, position: usize, (from, until): (u64, u64)) -> f32 {
+        let offset = self.times[position] as u64;
+        let left_by = |time: u64| {
+            let before = self
+                .departures
+                .binary_search_by(|&d| {
+                    if d + offset < time {
+                        Ordering::Less
+                    } else {
+                        Ordering::Greater
+                    }
+                })
+                .unwrap_or_else(|i| i);
+            if before == 0 {
+                0.0
+            } else {
+                self.counted[before - 1]
+            }
+        };
+        left_by(until) - left_by(from)
+    }
+}
+
+/// Every route pattern, and where each stop falls along them. A ride goes
+/// from any stop on a pattern to any later one, so the pairs aren't stored;
+/// they're gathered per stop for one headway window by `in_window` instead.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct PreConnections {
+    patterns: Vec<Pattern>,
+    // Each pattern through a stop, and how far along it the stop is
+    at_stop: HashMap<StopId, Vec<(usize, usize)>>,
+    // From the first departure to the last arrival, which is what headways
+    // are averaged over unless a window is given
+    service: (u64, u64),
+    // The rides from and to each stop, for the window last given to
+    // `in_window`. Quick to gather again, so they aren't cached.
+    #[serde(skip)]
+    departing: HashMap<StopId, Vec<(StopId, Connection)>>,
+    #[serde(skip)]
+    arriving: HashMap<StopId, Vec<(StopId, Connection)>>,
+}
+
+// The rides from or to one stop, gathered over every pattern through it.
+#[derive(Default)]
+struct Rides(HashMap<StopId, (Connection, f32)>);
+
+impl Rides {
+    fn add(&mut self, other: StopId, pattern: &Pattern, board: usize, alight: usize, trips: f32) {
+        let time = pattern.times[alight] - pattern.times[board];
+        let &mut (ref mut ride, ref mut count) = self.0.entry(other).or_insert((
+            Connection {
+                time: time,
+                trip_id: pattern.trip_id,
+                headway: 0.0,
+            },
+            0.0,
+        ));
+        if time < ride.time {
+            ride.time = time;
+            ride.trip_id = pattern.trip_id;
+        }
+        *count += trips;
+    }
+
+    fn finish(self, window: f32, except: StopId) -> Vec<(StopId, Connection)> {
+        self.0
+            .into_iter()
+            .filter(|&(other, _)| other != except)
+            .map(|(other, (mut ride, trips))| {
+                ride.headway = window / trips;
+                (other, ride)
+            })
+            .collect()
+    }
+}
+
+impl PreConnections {
+    /// Groups trips by the stops they make. Each is given as its trip_id, the
+    /// share of days it runs on, and its stops and times in order.
+    pub fn from_trips<I>(trips: I) -> Self
+    where
+        I: IntoIterator<Item = (u32, f32, Vec<(StopId, u64)>)>,
+    {
+        let mut out = PreConnections {
+            service: (u64::MAX, 0),
+            ..PreConnections::default()
+        };
+        let mut by_stops: HashMap<Vec<StopId>, usize> = HashMap::default();
+        let mut departures: Vec<Vec<(u64, f32)>> = Vec::new();
+        for (trip_id, share, trip) in trips {
+            if trip.len() < 2 {
+                continue;
+            }
+            let stops: Vec<StopId> = trip.iter().map(|&(stop, _)| stop).collect();
+            let (start, end) = (trip[0].1, trip[trip.len() - 1].1);
+            out.service = (out.service.0.min(start), out.service.1.max(end));
+            let times: Vec<f32> =
+                trip.iter().map(|&(_, time)| time.saturating_sub(start) as f32).collect();
+
+            let next = out.patterns.len();
+            let i = *by_stops.entry(stops.clone()).or_insert(next);
+            if i == next {
+                for (position, stop) in stops.iter().enumerate() {
+                    out.at_stop.entry(*stop).or_default().push((i, position));
+                }
+                out.patterns.push(Pattern {
+                    stops: stops,
+                    times: times,
+                    trip_id: trip_id,
+                    departures: vec![],
+                    counted: vec![],
+                });
+                departures.push(vec![]);
+            } else if times.last() < out.patterns[i].times.last() {
+                out.patterns[i].times = times;
+                out.patterns[i].trip_id = trip_id;
+            }
+            departures[i].push((start, share));
+        }
+
+        for (pattern, mut departures) in out.patterns.iter_mut().zip(departures) {
+            departures.sort_by_key(|&(time, _)| time);
+            let mut counted = 0.0;
+            for (time, share) in departures {
+                counted += share;
+                pattern.departures.push(time);
+                pattern.counted.push(counted);
+            }
         }
-        out.get_mut(&start).unwrap().insert(stop, info);
+        out.in_window(None)
+    }
+
+    /// Gathers the rides from and to every stop, with headways counting only
+    /// the trips leaving in `window`, or the whole service day if it isn't
+    /// given. Searches then look them up without going over the patterns.
+    pub fn in_window(mut self, window: Option<(u64, u64)>) -> Self {
+        let span = window.unwrap_or(self.service);
+        let window_len = span.1.saturating_sub(span.0).max(1) as f32;
+        let mut departing: HashMap<StopId, Rides> = HashMap::default();
+        let mut arriving: HashMap<StopId, Rides> = HashMap::default();
+        for pattern in &self.patterns {
+            for board in 0..pattern.stops.len() {
+                let trips = pattern.trips_between(board, span);
+                if trips <= 0.0 {
+                    continue;
+                }
+                let from = pattern.stops[board];
+                for alight in board + 1..pattern.stops.len() {
+                    let to = pattern.stops[alight];
+                    let rides = departing.entry(from).or_default();
+                    rides.add(to, pattern, board, alight, trips);
+                    let rides = arriving.entry(to).or_default();
+                    rides.add(from, pattern, board, alight, trips);
+                }
+            }
+        }
+        let finish = |rides: HashMap<StopId, Rides>| {
+            rides
+                .into_iter()
+                .map(|(stop, rides)| (stop, rides.finish(window_len, stop)))
+                .collect()
+        };
+        self.departing = finish(departing);
+        self.arriving = finish(arriving);
+        self
+    }
+
+    /// Every ride boarded at `from`, one to each stop it goes to: the fastest
+    /// of the patterns going there, with a headway counting the trips of them
+    /// all that leave `from` in the window.
+    pub fn departing(&self, from: StopId) -> &[(StopId, Connection)] {
+        self.departing.get(&from).map_or(&[], |rides| &rides[..])
     }
-    out
+
+    /// Every ride that gets off at `to`, one from each stop it comes from, the
+    /// same way as `departing`.
+    pub fn arriving(&self, to: StopId) -> &[(StopId, Connection)] {
+        self.arriving.get(&to).map_or(&[], |rides| &rides[..])
+    }
+
+    /// The ride from `from` to `to`, if there is one.
+    pub fn between(&self, from: StopId, to: StopId) -> Option<Connection> {
+        self.departing(from).iter().find(|&&(other, _)| other == to).map(|&(_, ride)| ride)
+    }
+
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+}
+
+/// Where a feed is read from and where its preprocessed form is cached.
+pub struct FeedOptions {
+    // Either a directory of GTFS tables or a zipped feed
+    pub data: PathBuf,
+    pub cache: PathBuf,
+    // Only trips running on this YYYYMMDD date are used, if set
+    pub service_date: Option<u32>,
+    pub projection: ProjectionKind,
+    // Latitude and longitude to center the projection (and so the map) on,
+    // rather than the middle of the feed's stops
+    pub center: Option<(f64, f64)>,
 }
 
-pub fn get_connections() -> (Stops, PreConnections) {
-    read_connections().unwrap_or_else(|_| build_connections())
+/// Identifies what a cache was built from.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FeedMeta {
+    // From the size and modification time of the feed's files
+    pub feed_stamp: u64,
+    pub service_date: Option<u32>,
+    pub projection: ProjectionKind,
+    pub center: Option<(f64, f64)>,
 }
 
-fn read_connections() -> Result<(Stops, PreConnections), Box<Error>> {
-    let file = BufReader::new(File::open("cache/stops.json")?);
-    let stops = from_reader(file)?;
+impl FeedOptions {
+    pub fn new(
+        data: PathBuf,
+        cache: PathBuf,
+        service_date: Option<u32>,
+        projection: ProjectionKind,
+        center: Option<(f64, f64)>,
+    ) -> Self {
+        FeedOptions {
+            data: data,
+            cache: cache,
+            service_date: service_date,
+            projection: projection,
+            center: center,
+        }
+    }
 
-    let file = BufReader::new(File::open("cache/connections.json")?);
-    let connections = from_reader(file)?;
+    pub fn cache_file(&self, name: &str) -> PathBuf {
+        self.cache.join(name)
+    }
 
-    Ok((stops, connections))
+    pub fn meta(&self) -> Result<FeedMeta, FeedError> {
+        let feed_stamp = cache::stamp_path(&self.data).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => FeedError::MissingFile(self.data.display().to_string()),
+            _ => FeedError::Io(e),
+        })?;
+        Ok(FeedMeta {
+            feed_stamp: feed_stamp,
+            service_date: self.service_date,
+            projection: self.projection,
+            center: self.center,
+        })
+    }
 }
 
-fn build_connections() -> (Stops, PreConnections) {
+pub type Network = (Stops, PreConnections, Projection);
+
+/// The stops and connections, with rides gathered over the headway `window`
+/// (see `PreConnections::in_window`).
+pub fn get_connections(
+    options: &FeedOptions,
+    window: Option<(u64, u64)>,
+) -> Result<Network, FeedError> {
+    let (stops, connections, projection) = cache::get_or_build(
+        &options.cache_file("connections.bin"),
+        &options.meta()?,
+        || build_connections(options),
+    )?;
+    Ok((stops, connections.in_window(window), projection))
+}
+
+/// Rebuilds the stops and connections from the feed, regardless of what's cached.
+pub fn rebuild_connections(
+    options: &FeedOptions,
+    window: Option<(u64, u64)>,
+) -> Result<Network, FeedError> {
+    let built = build_connections(options)?;
+    cache::write(&options.cache_file("connections.bin"), &options.meta()?, &built)?;
+    let (stops, connections, projection) = built;
+    Ok((stops, connections.in_window(window), projection))
+}
+
+/// Stops are placed in km around the center if one is given, or else the
+/// middle of the feed, which the returned projection converts to and from.
+fn build_connections(options: &FeedOptions) -> Result<Network, FeedError> {
+    let feed = load_feed(options)?;
+    let projection = match options.center {
+        Some(center) => Projection::new(options.projection, center),
+        None => Projection::around(
+            options.projection,
+            feed.stops.iter().map(|stop| (stop.stop_lat, stop.stop_lon)),
+        ),
+    };
+    // Only the trips running on the service date are left if it's given.
+    // Otherwise trips from every day are mixed together, and each counts for
+    // the share of days it runs on.
+    let shares = match options.service_date {
+        Some(_) => HashMap::default(),
+        None => feed.trip_shares(),
+    };
     let mut stops = HashMap::default();
-    for result in csv::Reader::from_path("data/stops.txt")
-        .unwrap()
-        .into_deserialize()
-    {
-        let result: RawStop = result.unwrap();
-        stops.insert(StopId(result.stop_id), Stop::new(result));
-    }
-    let mut stop_times = Vec::new();
-    for result in csv::Reader::from_path("data/stop_times.txt")
-        .unwrap()
-        .into_deserialize()
-        .map(Result::unwrap)
-    {
-        stop_times.push(result);
+    for raw_stop in feed.stops {
+        let id = StopId(feed.stop_ids[raw_stop.stop_id.as_str()]);
+        stops.insert(id, Stop::new(id, raw_stop, &projection));
     }
+    let connections = build_routes(&feed.stop_times, &shares);
+    Ok((stops, connections, projection))
+}
 
-    let connections = translate_connections(build_routes(&stop_times));
+pub fn get_timetable(options: &FeedOptions) -> Result<Timetable, FeedError> {
+    cache::get_or_build(
+        &options.cache_file("timetable.bin"),
+        &options.meta()?,
+        || build_timetable(options),
+    )
+}
 
-    {
-        let file = BufWriter::new(File::create("cache/stops.json").unwrap());
-        to_writer_pretty(file, &stops).unwrap();
+/// Rebuilds the timetable from the feed, regardless of what's cached.
+pub fn rebuild_timetable(options: &FeedOptions) -> Result<Timetable, FeedError> {
+    let timetable = build_timetable(options)?;
+    cache::write(&options.cache_file("timetable.bin"), &options.meta()?, &timetable)?;
+    Ok(timetable)
+}
+
+fn build_timetable(options: &FeedOptions) -> Result<Timetable, FeedError> {
+    Ok(build_schedule(&load_feed(options)?.stop_times))
+}
+
+pub fn get_trip_names(options: &FeedOptions) -> Result<TripNames, FeedError> {
+    cache::get_or_build(
+        &options.cache_file("trips.bin"),
+        &options.meta()?,
+        || Ok(load_feed(options)?.trip_names()),
+    )
+}
+
+/// Everything in the feed that had to be left out when loading it.
+pub fn validate(options: &FeedOptions) -> Result<Vec<FeedError>, FeedError> {
+    Ok(Feed::open(&options.data)?.skipped)
+}
+
+/// Loads the feed, keeping only the trips that run on the service date if one
+/// is given.
+fn load_feed(options: &FeedOptions) -> Result<Feed, FeedError> {
+    let mut feed = Feed::open(&options.data)?;
+    if !feed.skipped.is_empty() {
+        eprintln!(
+            "warning: skipped {} malformed or inconsistent parts of the feed; run `validate` \
+             to list them",
+            feed.skipped.len()
+        );
     }
-    {
-        let file = BufWriter::new(File::create("cache/connections.json").unwrap());
-        to_writer_pretty(file, &connections).unwrap();
+    if let Some(date) = options.service_date {
+        feed.retain_service_on(date)?;
+    }
+    Ok(feed)
+}
+
+/// Builds one connection per hop of every trip, each with the scheduled
+/// departure and arrival times, sorted by departure.
+fn build_schedule(times: &[StopTime]) -> Timetable {
+    let mut group_by_trip_id: HashMap<u32, Vec<&StopTime>> = HashMap::default();
+    for stop in times {
+        group_by_trip_id
+            .entry(stop.trip_id)
+            .or_default()
+            .push(stop);
     }
 
-    (stops, connections)
+    let mut connections = Vec::new();
+    for (trip_id, mut trip) in group_by_trip_id {
+        trip.sort_by_key(|stop| stop.stop_sequence);
+        for pair in trip.windows(2) {
+            let (departure, arrival) = (pair[0].departure, pair[1].arrival);
+            connections.push(TimedConnection {
+                from: StopId(pair[0].stop_id),
+                to: StopId(pair[1].stop_id),
+                departure: departure,
+                arrival: arrival,
+                trip_id: trip_id,
+            });
+        }
+    }
+    connections.sort_by_key(|c| c.departure);
+
+    println!("done building schedule");
+    Timetable {
+        connections: connections,
+    }
 }
 
-fn build_routes(times: &[RawStopTime]) -> Connections {
-    use std::collections::hash_map::Entry::*;
+/// Groups the trips into route patterns, counting each trip for its share in
+/// `shares`, or fully if it isn't there.
+fn build_routes(times: &[StopTime], shares: &HashMap<u32, f32>) -> PreConnections {
     let mut group_by_trip_id = HashMap::default();
     for stop in times {
         group_by_trip_id
             .entry(stop.trip_id)
             .or_insert_with(|| Vec::new())
-            .push(stop.clone());
+            .push(stop);
     }
 
-    let mut result: Connections = HashMap::default();
+    // Times keep counting past 24:00:00 on trips that run over midnight, so
+    // later stops are never earlier.
+    let trips = group_by_trip_id.into_iter().map(|(trip_id, mut trip)| {
+        trip.sort_by_key(|stop| stop.stop_sequence);
+        let stops = trip.iter().map(|stop| (StopId(stop.stop_id), stop.arrival)).collect();
+        (trip_id, shares.get(&trip_id).cloned().unwrap_or(1.0), stops)
+    });
+    let routes = PreConnections::from_trips(trips);
 
-    for (&trip_id, trip) in &group_by_trip_id {
-        for stop_one in trip {
-            for stop_two in trip {
-                if stop_one == stop_two {
-                    continue;
-                }
-
-                let stop_one_time = parse_time(&stop_one.arrival_time);
-                let stop_two_time = parse_time(&stop_two.arrival_time);
-                if stop_one.shape_dist_traveled >= stop_two.shape_dist_traveled
-                    || stop_one_time >= stop_two_time
-                {
-                    continue;
-                }
+    println!("done building routes: {} patterns", routes.patterns().len());
+    routes
+}
 
-                // Time can skip over midnight and go "backwards". Ignore those entries.
-                let time = stop_two_time - stop_one_time;
-                // this overwites a bunch (since there's many times per trip)
-                let route_info = Connection {
-                    time: time as f32,
-                    trip_id: trip_id,
-                };
-
-                match result.entry((StopId(stop_one.stop_id), StopId(stop_two.stop_id))) {
-                    Occupied(ref mut entry) => {
-                        let entry = entry.get_mut();
-                        if entry.time > time as f32 {
-                            *entry = route_info;
-                        }
-                    }
-                    Vacant(entry) => {
-                        entry.insert(route_info);
-                    }
-                }
-            }
-        }
+/// Parses an HH:MM:SS time into seconds since the start of the service day,
+/// or `None` if it isn't one.
+pub fn parse_time(time: &str) -> Option<u64> {
+    let mut split = time.trim().split(':');
+    let hours = split.next()?.parse::<u64>().ok()?;
+    let minutes = split.next()?.parse::<u64>().ok()?;
+    let seconds = split.next()?.parse::<u64>().ok()?;
+    if split.next().is_some() || minutes >= 60 || seconds >= 60 {
+        return None;
     }
+    Some(hours * (60 * 60) + minutes * 60 + seconds)
+}
 
-    println!("done building routes");
-    return result;
-}
-
-fn parse_time(time: &str) -> u64 {
-    let mut split = time.split(':');
-    let hours = split
-        .next()
-        .expect("Missing hours in time")
-        .parse::<u64>()
-        .expect("Hours invalid format");
-    let minutes = split
-        .next()
-        .expect("Missing minutes in time")
-        .parse::<u64>()
-        .expect("Minutes invalid format");
-    let seconds = split
-        .next()
-        .expect("Missing seconds in time")
-        .parse::<u64>()
-        .expect("Seconds invalid format");
-    assert!(split.next().is_none());
-    hours * (60 * 60) + minutes * 60 + seconds
-}
-
-pub fn lat_lon_to_x_y(lat: f64, lon: f64) -> (f64, f64) {
-    let delta_lat = lat - ORIGIN_LAT;
-    let delta_lon = lon - ORIGIN_LON;
-    let skew = (ORIGIN_LAT * (::std::f64::consts::PI / 180.0)).cos(); // approx. 1.0/1.48
-    let x = delta_lon * skew * (40_075.0 / 360.0);
-    let y = delta_lat * (40_075.0 / 360.0);
-    (x, y)
+/// Formats seconds since the start of the service day as "HH:MM".
+pub fn format_time(time: u64) -> String {
+    format!("{:02}:{:02}", time / (60 * 60), time / 60 % 60)
 }
 
 impl Stop {
-    fn new(raw_stop: RawStop) -> Self {
-        // Convert lat/lon to km around an origin, assuming locally flat earth.
-        let (x, y) = lat_lon_to_x_y(raw_stop.stop_lat, raw_stop.stop_lon);
+    fn new(id: StopId, raw_stop: RawStop, projection: &Projection) -> Self {
+        let (x, y) = projection.to_x_y(raw_stop.stop_lat, raw_stop.stop_lon);
         Stop {
-            stop_id: StopId(raw_stop.stop_id),
+            stop_id: id,
             stop_x: x,
             stop_y: y,
             name: raw_stop.stop_name,
         }
     }
 }
+
+#[test]
+fn parse_time_past_midnight() {
+    assert_eq!(parse_time("25:10:00"), Some(25 * 60 * 60 + 10 * 60));
+    assert_eq!(parse_time("24:00:00"), Some(24 * 60 * 60));
+    assert_eq!(parse_time("47:59:59"), Some(48 * 60 * 60 - 1));
+    assert_eq!(parse_time("23:60:00"), None);
+    assert_eq!(parse_time("25:10"), None);
+}
+
+#[test]
+fn overnight_connections() {
+    let stop = |stop_id, time: &str| {
+        let time = parse_time(time).unwrap();
+        StopTime {
+            trip_id: 1,
+            stop_id: stop_id,
+            arrival: time,
+            departure: time,
+            stop_sequence: stop_id,
+        }
+    };
+    let times = vec![stop(1, "23:50:00"), stop(2, "24:05:00"), stop(3, "25:10:00")];
+
+    let routes = build_routes(&times, &HashMap::default());
+    let routes: HashMap<StopId, Connection> = routes.departing(StopId(1)).iter().cloned().collect();
+    assert_eq!(routes[&StopId(2)].time, 15.0 * 60.0);
+    assert_eq!(routes[&StopId(3)].time, 80.0 * 60.0);
+
+    let timetable = build_schedule(&times);
+    let departures: Vec<u64> = timetable.connections.iter().map(|c| c.departure).collect();
+    assert_eq!(departures, vec![parse_time("23:50:00").unwrap(), parse_time("24:05:00").unwrap()]);
+}
+
+#[test]
+fn trips_share_patterns() {
+    let stop = |trip_id, stop_id, time: u64| StopTime {
+        trip_id: trip_id,
+        stop_id: stop_id,
+        arrival: time,
+        departure: time,
+        stop_sequence: stop_id,
+    };
+    let times = vec![
+        stop(1, 1, 0),
+        stop(1, 2, 300),
+        stop(1, 3, 900),
+        stop(2, 1, 1800),
+        stop(2, 2, 2400),
+        stop(2, 3, 2700),
+        stop(3, 2, 3600),
+        stop(3, 3, 3900),
+    ];
+
+    let routes = build_routes(&times, &HashMap::default());
+    assert_eq!(routes.patterns().len(), 2);
+    let from_one: HashMap<StopId, Connection> =
+        routes.departing(StopId(1)).iter().cloned().collect();
+    // Each trip is fastest over one of the hops, but no trip is faster overall.
+    assert_eq!(from_one[&StopId(3)].time, 900.0);
+    assert_eq!(from_one[&StopId(3)].headway, 1950.0);
+    // Trips of both patterns go from 2 to 3.
+    let ride = routes.between(StopId(2), StopId(3)).unwrap();
+    assert_eq!((ride.time, ride.headway), (300.0, 1300.0));
+    assert_eq!(routes.arriving(StopId(3)).len(), 2);
+}
+
+#[test]
+fn headways_by_window() {
+    let hour = 60 * 60;
+    // Every 10 minutes from 07:00 to 08:50, then hourly from 20:00 to 23:00.
+    let starts = (0..12).map(|i| 7 * hour + i * 600).chain((20..24).map(|h| h * hour));
+    let mut times = vec![];
+    for (trip_id, start) in starts.enumerate() {
+        for stop_id in 1..3 {
+            let time = start + (stop_id as u64 - 1) * 300;
+            times.push(StopTime {
+                trip_id: trip_id as u32,
+                stop_id: stop_id,
+                arrival: time,
+                departure: time,
+                stop_sequence: stop_id,
+            });
+        }
+    }
+    let headway = |routes: &PreConnections, from: u64, until: u64| {
+        let routes = routes.clone().in_window(Some((from * hour, until * hour)));
+        routes.between(StopId(1), StopId(2)).map(|ride| ride.headway)
+    };
+
+    let routes = build_routes(&times, &HashMap::default());
+    assert_eq!(headway(&routes, 7, 9), Some(600.0));
+    assert_eq!(headway(&routes, 20, 24), Some(3600.0));
+    assert_eq!(headway(&routes, 3, 5), None);
+
+    // Trips running every other day come half as often on average.
+    let shares = (0..16).map(|trip_id| (trip_id, 0.5)).collect();
+    let routes = build_routes(&times, &shares);
+    assert_eq!(headway(&routes, 7, 9), Some(1200.0));
+}
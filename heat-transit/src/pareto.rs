@@ -0,0 +1,145 @@
+use fnv::FnvHashMap as HashMap;
+use model::{PreConnections, StopId, Stops};
+use pathing::{boarding, walking_time, Frontier, MAX_WALK_DISTANCE, MAX_WALK_TIME};
+use spatial::StopIndex;
+use std::collections::BinaryHeap;
+use time::{CostModel, TimeCost};
+
+/// For every stop, every way to the destination that isn't beaten on travel
+/// time, walking and transfers all at once. Sorted by travel time.
+pub type Bags = HashMap<StopId, Vec<TimeCost>>;
+
+/// Whether `a` is at least as good as `b` on every criterion.
+pub fn dominates(a: &TimeCost, b: &TimeCost) -> bool {
+    a.total() <= b.total() && a.walk_time <= b.walk_time && a.transfers <= b.transfers
+}
+
+// Since labels are added in order of travel time, a new label only has to be
+// checked against the ones already there.
+fn dominated(bag: &[TimeCost], cost: &TimeCost) -> bool {
+    bag.iter().any(|kept| dominates(kept, cost))
+}
+
+/// Multi-criteria search backwards from `end`, over the same moves as
+/// `pathing::search_to`. Only the transfer limit and wait strategy of
+/// `cost_model` are used, since every criterion is kept apart.
+/// Unlike it, every walk (including the last one) has to be shorter than
+/// `MAX_WALK_TIME`, and there's no more walking between stops once that much
+/// walking has been done. This keeps the bags small.
+pub fn search_to(
+    stops: &Stops,
+    index: &StopIndex,
+    connections: &PreConnections,
+    end: (f64, f64),
+    cost_model: &CostModel,
+) -> Bags {
+    let limit = CostModel {
+        max_transfers: cost_model.max_transfers,
+        wait: cost_model.wait,
+        ..CostModel::default()
+    };
+
+    let mut bags: Bags = HashMap::default();
+    let mut frontier = BinaryHeap::new();
+    for (id, coords) in index.near(end, MAX_WALK_DISTANCE) {
+        let walk = walking_time(coords, end);
+        if walk.walk_time < MAX_WALK_TIME {
+            frontier.push(Frontier(walk, id));
+        }
+    }
+
+    while let Some(Frontier(cost, id)) = frontier.pop() {
+        {
+            let bag = bags.entry(id).or_default();
+            if dominated(bag, &cost) {
+                continue;
+            }
+            bag.push(cost);
+        }
+
+        let mut push = |from: StopId, next: TimeCost| {
+            if !bags.get(&from).is_some_and(|bag| dominated(bag, &next)) {
+                frontier.push(Frontier(next, from));
+            }
+        };
+
+        if cost.walk_time <= MAX_WALK_TIME {
+            let here = (stops[&id].stop_x, stops[&id].stop_y);
+            for (other, coords) in index.near(here, MAX_WALK_DISTANCE) {
+                let walk = walking_time(coords, here);
+                if other != id && walk.walk_time < MAX_WALK_TIME {
+                    push(other, walk + cost);
+                }
+            }
+        }
+        for &(from, info) in connections.arriving(id) {
+            if let Some(ride) = boarding(&limit, &cost, &info) {
+                push(from, ride + cost);
+            }
+        }
+    }
+    bags
+}
+
+/// Every trade-off between travel time, walking and transfers for getting from
+/// `start` to `end`, given the bags from `search_to`. Sorted by travel time,
+/// so the first is the fastest. Walking straight there counts only if it's
+/// shorter than `MAX_WALK_TIME`, like every other walk.
+pub fn options(
+    index: &StopIndex,
+    bags: &Bags,
+    start: (f64, f64),
+    end: (f64, f64),
+) -> Vec<TimeCost> {
+    let mut candidates = vec![];
+    let direct = walking_time(start, end);
+    if direct.walk_time < MAX_WALK_TIME {
+        candidates.push(direct);
+    }
+    for (id, coords) in index.near(start, MAX_WALK_DISTANCE) {
+        let walk = walking_time(start, coords);
+        if walk.walk_time >= MAX_WALK_TIME {
+            continue;
+        }
+        if let Some(bag) = bags.get(&id) {
+            candidates.extend(bag.iter().map(|&rest| walk + rest));
+        }
+    }
+    candidates.sort();
+
+    let mut kept: Vec<TimeCost> = vec![];
+    for candidate in candidates {
+        if !dominated(&kept, &candidate) {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+/// The fastest of `options` with as few transfers as any of them.
+pub fn fewest_transfers(options: &[TimeCost]) -> Option<TimeCost> {
+    options.iter().min_by_key(|option| option.transfers).cloned()
+}
+
+#[test]
+fn finds_trade_offs() {
+    use pathing::branches;
+    let (stops, connections, index) = branches();
+    let end = (10.0, 0.0);
+    let bags = search_to(&stops, &index, &connections, end, &CostModel::default());
+
+    // Changing at stop 2 is quicker than the slow bus straight there.
+    let found = options(&index, &bags, (0.0, 0.0), end);
+    let summary: Vec<_> = found.iter().map(|o| (o.total(), o.transfers)).collect();
+    assert_eq!(summary, vec![(3000.0, 1), (3300.0, 0)]);
+    assert_eq!(fewest_transfers(&found).map(|o| o.transfers), Some(0));
+
+    // Nowhere near a stop, and too far to walk
+    assert!(options(&index, &bags, (25.0, 0.0), end).is_empty());
+    let limited = CostModel {
+        max_transfers: Some(0),
+        ..CostModel::default()
+    };
+    let bags = search_to(&stops, &index, &connections, end, &limited);
+    assert_eq!(options(&index, &bags, (0.0, 0.0), end).len(), 1);
+}
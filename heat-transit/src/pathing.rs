@@ -1,13 +1,19 @@
 use astar::SearchProblem;
-use model::{lat_lon_to_x_y, PreConnections, StopId, Stops};
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+use model::{Connection, PreConnections, StopId, Stops};
+use num_traits::Zero;
 use precache::RouteCache;
+use spatial::StopIndex;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use std::hash::{Hash, Hasher};
-use time::TimeCost;
+use streets::StreetWalks;
+use time::{CostModel, TimeCost};
 
-const WALKING_SPEED: f64 = 0.0014;
+pub const WALKING_SPEED: f64 = 0.0014;
 const DRIVING_SPEED: f64 = 0.0178;
-const BUS_WAIT_TIME: f32 = 7.5 * 60.0; // 7.5 minutes
-const MAX_WALK_TIME: f32 = 20.0 * 60.0; // 1 hour
+pub const MAX_WALK_TIME: f32 = 20.0 * 60.0; // 1 hour
+pub const MAX_WALK_DISTANCE: f64 = MAX_WALK_TIME as f64 * WALKING_SPEED; // km
 
 fn travel_time((ax, ay): (f64, f64), (bx, by): (f64, f64), speed: f64) -> f64 {
     let dx = ax - bx;
@@ -20,10 +26,32 @@ pub fn walking_time(s: (f64, f64), e: (f64, f64)) -> TimeCost {
     TimeCost::of_walking(travel_time(s, e, WALKING_SPEED) as f32)
 }
 
+/// How long walking `km` takes.
+pub fn walking_time_for(km: f64) -> TimeCost {
+    TimeCost::of_walking((km / WALKING_SPEED) as f32)
+}
+
 fn driving_time(s: (f64, f64), e: (f64, f64)) -> TimeCost {
     TimeCost::of_bus(travel_time(s, e, DRIVING_SPEED) as f32)
 }
 
+fn riding_time(cost_model: &CostModel, info: &Connection) -> TimeCost {
+    TimeCost::of_bus(info.time) + TimeCost::of_waiting(cost_model.wait.expected_wait(info.headway))
+}
+
+/// The cost of riding `info` for someone who has already travelled `cost`, or
+/// `None` if the model doesn't allow another transfer.
+pub fn boarding(cost_model: &CostModel, cost: &TimeCost, info: &Connection) -> Option<TimeCost> {
+    if !cost_model.can_board(cost) {
+        return None;
+    }
+    let mut ride = riding_time(cost_model, info);
+    if cost.has_boarded() {
+        ride = ride + TimeCost::of_transfer();
+    }
+    Some(cost_model.weigh(ride))
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum HowGet {
     Walk,
@@ -32,8 +60,9 @@ pub enum HowGet {
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Position {
-    BusStop(StopId, HowGet),
-    LatLon(f64, f64),
+    // How the stop was got to, and the `CostModel::boardings` on the way there
+    BusStop(StopId, HowGet, u32),
+    // km around the projection's origin
     Custom(f64, f64),
 }
 
@@ -41,8 +70,7 @@ impl Position {
     pub fn get_coords(&self, stops: &Stops) -> (f64, f64) {
         match self {
             Position::Custom(x, y) => (*x, *y),
-            Position::LatLon(lat, lon) => lat_lon_to_x_y(*lat, *lon),
-            Position::BusStop(id, _) => {
+            Position::BusStop(id, _, _) => {
                 let stop = &stops[id];
                 (stop.stop_x, stop.stop_y)
             }
@@ -57,11 +85,7 @@ impl Hash for Position {
         H: Hasher,
     {
         match self {
-            Position::BusStop(StopId(id), _) => state.write_u32(*id),
-            Position::LatLon(a, b) => {
-                state.write_u64(a.to_bits());
-                state.write_u64(b.to_bits());
-            }
+            Position::BusStop(StopId(id), _, _) => state.write_u32(*id),
             Position::Custom(a, b) => {
                 state.write_u64(a.to_bits());
                 state.write_u64(b.to_bits());
@@ -73,8 +97,45 @@ impl Hash for Position {
 pub struct TransitSearchProblem<'a> {
     pub stops: &'a Stops,
     pub connections: &'a PreConnections,
+    pub index: &'a StopIndex,
     pub end: Position,
     pub precache: RouteCache,
+    pub cost_model: CostModel,
+    // Walk along the streets rather than in straight lines, if set
+    pub walks: Option<&'a StreetWalks<'a>>,
+}
+
+impl<'a> TransitSearchProblem<'a> {
+    /// How long walking from `cur` to the end takes, if it's possible, and
+    /// every stop that can be walked to from `cur`.
+    fn walks_from(&self, cur: &Position) -> (Option<TimeCost>, Vec<(StopId, TimeCost)>) {
+        let cur_coords = cur.get_coords(self.stops);
+        match (self.walks, cur) {
+            (Some(walks), &Position::BusStop(id, _, _)) => {
+                (walks.to_end(cur_coords), walks.footpaths(id).to_vec())
+            }
+            (Some(walks), _) => (walks.to_end(cur_coords), walks.to_stops(cur_coords)),
+            (None, _) => {
+                let end_coords = self.end.get_coords(self.stops);
+                let nearby = self
+                    .index
+                    .near(cur_coords, MAX_WALK_DISTANCE)
+                    .map(|(id, coords)| (id, walking_time(cur_coords, coords)))
+                    .filter(|&(_, walk)| walk.walk_time < MAX_WALK_TIME)
+                    .collect();
+                (Some(walking_time(cur_coords, end_coords)), nearby)
+            }
+        }
+    }
+
+    /// How long walking between two places takes, the same way the search does.
+    pub fn walk(&self, from: &Position, to: &Position) -> Option<TimeCost> {
+        let (from, to) = (from.get_coords(self.stops), to.get_coords(self.stops));
+        match self.walks {
+            Some(walks) => walks.streets().walk(from, to),
+            None => Some(walking_time(from, to)),
+        }
+    }
 }
 
 impl<'a> SearchProblem for TransitSearchProblem<'a> {
@@ -85,15 +146,20 @@ impl<'a> SearchProblem for TransitSearchProblem<'a> {
         a == &self.end
     }
     fn heuristic(&self, p: &Self::Node) -> Self::Cost {
-        if let Position::BusStop(id, _) = p {
+        if let Position::BusStop(id, _, _) = p {
             if let Some(&r) = self.precache.get(id) {
                 return r;
             }
         }
 
+        // Nothing goes faster than driving, but walking can count for so much
+        // less than riding that it's cheaper still, so guess the cheaper one.
         let p = p.get_coords(self.stops);
         let g = self.end.get_coords(self.stops);
-        driving_time(g, p)
+        ::std::cmp::min(
+            self.cost_model.weigh(driving_time(g, p)),
+            self.cost_model.weigh(walking_time(g, p)),
+        )
     }
 
     fn neighbors(&self, cur: &Self::Node, cost: &Self::Cost) -> Self::Iter {
@@ -102,32 +168,495 @@ impl<'a> SearchProblem for TransitSearchProblem<'a> {
         }
 
         let mut neighbors = vec![];
-        let end_coords = self.end.get_coords(&self.stops);
-        let cur_coords = cur.get_coords(&self.stops);
+        let (to_end, to_stops) = self.walks_from(cur);
 
         // Walk to the end
-        let walk_time = walking_time(cur_coords, end_coords);
-        neighbors.push((self.end, walk_time));
-
-        // Walk to every bus stop
-        for (id, stop) in self.stops {
-            let walk_time = walking_time(cur_coords, (stop.stop_x, stop.stop_y));
-            if walk_time.walk_time < MAX_WALK_TIME {
-                neighbors.push((Position::BusStop(*id, HowGet::Walk), walk_time))
-            }
+        if let Some(walk_time) = to_end {
+            neighbors.push((self.end, self.cost_model.weigh(walk_time)));
+        }
+
+        // Walk to every bus stop nearby
+        let boardings = self.cost_model.boardings(cost);
+        for (id, walk_time) in to_stops {
+            let walk_time = self.cost_model.weigh(walk_time);
+            neighbors.push((Position::BusStop(id, HowGet::Walk, boardings), walk_time))
         }
 
         // If at a bus stop, travel to other things on the route
-        if let Position::BusStop(id, _) = cur {
-            if self.connections.contains_key(id) {
-                for (end, info) in &self.connections[id] {
-                    neighbors.push((
-                        Position::BusStop(*end, HowGet::Bus),
-                        TimeCost::of_bus(info.time) + TimeCost::of_waiting(BUS_WAIT_TIME),
-                    ));
+        if let Position::BusStop(id, _, _) = cur {
+            for &(end, info) in self.connections.departing(*id) {
+                if let Some(ride) = boarding(&self.cost_model, cost, &info) {
+                    let boardings = self.cost_model.boardings(&(*cost + ride));
+                    neighbors.push((Position::BusStop(end, HowGet::Bus, boardings), ride));
                 }
             }
         }
         neighbors.into_iter()
     }
 }
+
+/// The same search with no heuristic, which makes `astar` plain Dijkstra:
+/// slower, but right whatever the heuristic would have guessed.
+pub struct Unguided<'a, S: 'a>(pub &'a S);
+
+impl<'a, S: SearchProblem> SearchProblem for Unguided<'a, S> {
+    type Node = S::Node;
+    type Cost = S::Cost;
+    type Iter = S::Iter;
+    fn is_end(&self, a: &Self::Node) -> bool {
+        self.0.is_end(a)
+    }
+    fn heuristic(&self, _: &Self::Node) -> Self::Cost {
+        Self::Cost::zero()
+    }
+    fn neighbors(&self, cur: &Self::Node, cost: &Self::Cost) -> Self::Iter {
+        self.0.neighbors(cur, cost)
+    }
+}
+
+// Orders the search frontier so that `BinaryHeap` pops the cheapest first.
+#[derive(PartialEq, Eq)]
+pub struct Frontier(pub TimeCost, pub StopId);
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Dijkstra from `start` to every stop that can be reached within `limit`
+/// seconds, using the same moves as `TransitSearchProblem`. Unlike running
+/// `astar` per destination, the result can be shared by every destination.
+pub fn search_all(
+    stops: &Stops,
+    index: &StopIndex,
+    connections: &PreConnections,
+    cost_model: &CostModel,
+    start: (f64, f64),
+    limit: f32,
+) -> HashMap<StopId, TimeCost> {
+    let mut best: HashMap<StopId, TimeCost> = HashMap::default();
+    // The buses already taken can change what boarding another costs, so
+    // every stop is settled once for each `CostModel::boardings`.
+    let mut settled: HashSet<(StopId, u32)> = HashSet::default();
+    let mut frontier = BinaryHeap::new();
+    for (id, coords) in index.near(start, MAX_WALK_DISTANCE) {
+        let walk = cost_model.weigh(walking_time(start, coords));
+        if walk.walk_time < MAX_WALK_TIME && walk.total() <= limit {
+            frontier.push(Frontier(walk, id));
+        }
+    }
+
+    while let Some(Frontier(cost, id)) = frontier.pop() {
+        if !settled.insert((id, cost_model.boardings(&cost))) {
+            continue;
+        }
+        best.entry(id).or_insert(cost);
+
+        let mut push = |to: StopId, next: TimeCost| {
+            if next.total() <= limit && !settled.contains(&(to, cost_model.boardings(&next))) {
+                frontier.push(Frontier(next, to));
+            }
+        };
+
+        if cost.walk_time <= MAX_WALK_TIME {
+            let here = (stops[&id].stop_x, stops[&id].stop_y);
+            for (other, coords) in index.near(here, MAX_WALK_DISTANCE) {
+                let walk = cost_model.weigh(walking_time(here, coords));
+                if walk.walk_time < MAX_WALK_TIME {
+                    push(other, cost + walk);
+                }
+            }
+        }
+        for &(end, info) in connections.departing(id) {
+            if let Some(ride) = boarding(cost_model, &cost, &info) {
+                push(end, cost + ride);
+            }
+        }
+    }
+    best
+}
+
+/// The fastest way to `end`, given the fastest way to every stop from `search_all`.
+pub fn best_from_tree(
+    index: &StopIndex,
+    cost_model: &CostModel,
+    tree: &HashMap<StopId, TimeCost>,
+    start: (f64, f64),
+    end: (f64, f64),
+) -> TimeCost {
+    let mut best = cost_model.weigh(walking_time(start, end));
+    for (id, coords) in index.near(end, MAX_WALK_DISTANCE) {
+        if let Some(&cost) = tree.get(&id) {
+            let walk = cost_model.weigh(walking_time(coords, end));
+            if walk.walk_time < MAX_WALK_TIME {
+                best = ::std::cmp::min(best, cost + walk);
+            }
+        }
+    }
+    best
+}
+
+/// The ways from every stop to the end that `search_to` found, cheapest first
+/// and each walking less than the ones before it. Each comes with how long it
+/// walks before its last walk to the end, which counts towards the limit on
+/// walking.
+pub type ReverseTree = HashMap<StopId, Vec<(TimeCost, f32)>>;
+
+/// Dijkstra backwards from `end`, giving the fastest ways from every stop to
+/// `end` using the same moves as `TransitSearchProblem`. With this, the
+/// fastest way from anywhere is `best_to_tree`, since walking takes as long
+/// in either direction.
+pub fn search_to(
+    stops: &Stops,
+    index: &StopIndex,
+    connections: &PreConnections,
+    cost_model: &CostModel,
+    end: (f64, f64),
+) -> ReverseTree {
+    // Any stop can walk straight to the end, however far away it is, and
+    // that last walk doesn't count towards the limit.
+    let mut tree: ReverseTree = HashMap::default();
+    let mut frontier: BinaryHeap<_> = stops
+        .iter()
+        .map(|(id, stop)| {
+            let walk = cost_model.weigh(walking_time((stop.stop_x, stop.stop_y), end));
+            Label(walk, 0.0, *id)
+        })
+        .collect();
+    // The least walking of any route from each stop found so far, for each
+    // `CostModel::boardings` of the buses it takes, since those change what
+    // boarding one more before them costs. Routes come off the frontier
+    // cheapest first, so a later one is only worth going on with if it walks
+    // less, leaving more of the limit for getting to it.
+    let mut least_walked: HashMap<(StopId, u32), f32> = HashMap::default();
+    let walks_less = |least: &HashMap<(StopId, u32), f32>, at: (StopId, u32), walked: f32| {
+        least.get(&at).is_none_or(|&least| walked < least)
+    };
+
+    while let Some(Label(cost, walked, id)) = frontier.pop() {
+        let boardings = cost_model.boardings(&cost);
+        if !walks_less(&least_walked, (id, boardings), walked) {
+            continue;
+        }
+        least_walked.insert((id, boardings), walked);
+        let ways = tree.entry(id).or_default();
+        if ways.last().is_none_or(|&(_, least)| walked < least) {
+            ways.push((cost, walked));
+        }
+
+        let here = (stops[&id].stop_x, stops[&id].stop_y);
+        for (other, coords) in index.near(here, MAX_WALK_DISTANCE) {
+            let walk = cost_model.weigh(walking_time(coords, here));
+            let walked = walked + walk.walk_time;
+            if walk.walk_time < MAX_WALK_TIME
+                && walked <= MAX_WALK_TIME
+                && walks_less(&least_walked, (other, boardings), walked)
+            {
+                frontier.push(Label(walk + cost, walked, other));
+            }
+        }
+        // Whether this ride is a transfer depends on what comes after it,
+        // which is what `cost` covers here.
+        for &(from, info) in connections.arriving(id) {
+            if let Some(ride) = boarding(cost_model, &cost, &info) {
+                let next = ride + cost;
+                if walks_less(&least_walked, (from, cost_model.boardings(&next)), walked) {
+                    frontier.push(Label(next, walked, from));
+                }
+            }
+        }
+    }
+    tree
+}
+
+/// The fastest way from `start` to the end of `tree`: walking straight there,
+/// or to a stop nearby and on from it. Like `astar`, the walk to the stop
+/// counts towards the limit on walking.
+pub fn best_to_tree(
+    index: &StopIndex,
+    cost_model: &CostModel,
+    tree: &ReverseTree,
+    start: (f64, f64),
+    end: (f64, f64),
+) -> TimeCost {
+    let mut best = cost_model.weigh(walking_time(start, end));
+    for (id, coords) in index.near(start, MAX_WALK_DISTANCE) {
+        let walk = cost_model.weigh(walking_time(start, coords));
+        if walk.walk_time >= MAX_WALK_TIME {
+            continue;
+        }
+        let ways = match tree.get(&id) {
+            Some(ways) => ways,
+            None => continue,
+        };
+        // Cheapest first, so the first that's within the limit is the best.
+        let within = ways.iter().find(|&&(_, walked)| walk.walk_time + walked <= MAX_WALK_TIME);
+        if let Some(&(cost, _)) = within {
+            best = ::std::cmp::min(best, walk + cost);
+        }
+    }
+    best
+}
+
+// A route from a stop to the end in `search_to`, with how long it walks
+// before the last walk to the end. Ordered like `Frontier`.
+struct Label(TimeCost, f32, StopId);
+
+impl PartialEq for Label {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Label {}
+
+impl Ord for Label {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl PartialOrd for Label {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+fn network(places: &[(f64, f64)], lines: &[&[(u32, u64)]]) -> (Stops, PreConnections, StopIndex) {
+    use model::Stop;
+    // Stops numbered from 1 in the order of `places`, and every line running
+    // twice, 20 minutes apart, with the stops and times since its departure
+    // given.
+    let mut stops = HashMap::default();
+    for (i, &(x, y)) in places.iter().enumerate() {
+        let id = StopId(i as u32 + 1);
+        let stop = Stop {
+            stop_id: id,
+            stop_x: x,
+            stop_y: y,
+            name: format!("Stop {}", i + 1),
+        };
+        stops.insert(id, stop);
+    }
+    let mut trips = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        for run in 0..2 {
+            let trip = line.iter().map(|&(stop, time)| (StopId(stop), run * 1200 + time)).collect();
+            trips.push((2 * i as u32 + run as u32, 1.0, trip));
+        }
+    }
+    let connections = PreConnections::from_trips(trips);
+    let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+    (stops, connections, index)
+}
+
+#[cfg(test)]
+fn line() -> (Stops, PreConnections, StopIndex) {
+    // Three stops 5 km apart, too far to walk between, on a bus taking ten
+    // minutes per hop and running every 20 minutes.
+    network(&[(0.0, 0.0), (5.0, 0.0), (10.0, 0.0)], &[&[(1, 0), (2, 600), (3, 1200)]])
+}
+
+#[cfg(test)]
+pub fn branches() -> (Stops, PreConnections, StopIndex) {
+    // Stops 5 km apart in a row, with quick buses from 1 to 2 and 2 to 3, a
+    // slow one straight from 1 to 3, and one on from 3 to 4.
+    let places = [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0), (15.0, 0.0)];
+    let lines: [&[(u32, u64)]; 4] = [
+        &[(1, 0), (2, 600)],
+        &[(2, 0), (3, 600)],
+        &[(1, 0), (3, 2400)],
+        &[(3, 0), (4, 600)],
+    ];
+    network(&places, &lines)
+}
+
+#[test]
+fn boarding_charges_transfers() {
+    let info = Connection {
+        time: 600.0,
+        trip_id: 1,
+        headway: 1200.0,
+    };
+    let model = CostModel::default();
+    let first = boarding(&model, &TimeCost::zero(), &info).unwrap();
+    assert_eq!((first.bus_time, first.wait_time, first.transfers), (600.0, 600.0, 0));
+    let second = boarding(&model, &first, &info).unwrap();
+    assert_eq!(second.transfers, 1);
+
+    let limited = CostModel {
+        max_transfers: Some(0),
+        ..CostModel::default()
+    };
+    assert!(boarding(&limited, &TimeCost::zero(), &info).is_some());
+    assert!(boarding(&limited, &first, &info).is_none());
+}
+
+#[test]
+fn searches_agree_both_ways() {
+    let (stops, connections, index) = line();
+    let model = CostModel::default();
+    let (start, end) = ((0.0, 0.0), (10.0, 0.0));
+
+    let forward = search_all(&stops, &index, &connections, &model, start, 60.0 * 60.0);
+    assert_eq!(forward[&StopId(3)].bus_time, 1200.0);
+    assert_eq!(forward[&StopId(3)].wait_time, 600.0);
+    let backward = search_to(&stops, &index, &connections, &model, end);
+    assert_eq!(backward[&StopId(1)][0].0.total(), forward[&StopId(3)].total());
+
+    let there = best_from_tree(&index, &model, &forward, start, end);
+    let back = best_to_tree(&index, &model, &backward, start, end);
+    assert_eq!(there.total(), 1800.0);
+    assert_eq!(there.total(), back.total());
+}
+
+#[test]
+fn heuristics_never_over_estimate() {
+    use time::WaitStrategy;
+    let models = [
+        CostModel::default(),
+        CostModel {
+            walk_weight: 0.05,
+            ..CostModel::default()
+        },
+        CostModel {
+            walk_weight: 3.0,
+            ride_weight: 0.5,
+            wait_weight: 2.0,
+            ..CostModel::default()
+        },
+        CostModel {
+            transfer_penalty: 600.0,
+            ..CostModel::default()
+        },
+        CostModel {
+            max_transfers: Some(1),
+            wait: WaitStrategy::Constant(0.0),
+            ..CostModel::default()
+        },
+    ];
+    for &(ref network, end) in &[(line(), (10.0, 0.0)), (branches(), (15.0, 0.0))] {
+        let (ref stops, ref connections, ref index) = *network;
+        for model in &models {
+            let actual: RouteCache = search_to(stops, index, connections, model, end)
+                .into_iter()
+                .map(|(id, ways)| (id, ways[0].0))
+                .collect();
+            let mut searcher = TransitSearchProblem {
+                stops: stops,
+                connections: connections,
+                index: index,
+                end: Position::Custom(end.0, end.1),
+                precache: HashMap::default(),
+                cost_model: *model,
+                walks: None,
+            };
+
+            // Driving or walking there, and then the route cache, which is
+            // exact for the stops in it.
+            for _ in 0..2 {
+                for (id, cost) in &actual {
+                    let at = Position::BusStop(*id, HowGet::Walk, 0);
+                    assert!(searcher.heuristic(&at) <= *cost, "{:?} at {:?}", model, id);
+                    assert_eq!(Unguided(&searcher).heuristic(&at), TimeCost::zero());
+                }
+                searcher.precache = actual.clone();
+            }
+            assert_eq!(searcher.heuristic(&searcher.end), TimeCost::zero());
+        }
+    }
+}
+
+#[test]
+fn reverse_search_limits_walking() {
+    // Stops 1 and 2 are a 12 minute walk apart, as are 2 and 3, and a bus
+    // runs from 3 to 4 right by the end. Walking on from 2 to 3 after
+    // walking there from 1 is over the limit.
+    let places = [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (7.0, 0.0)];
+    let (stops, connections, index) = network(&places, &[&[(3, 0), (4, 600)]]);
+    let model = CostModel::default();
+    let end = (7.0, 0.5);
+    let tree = search_to(&stops, &index, &connections, &model, end);
+    assert_eq!(tree[&StopId(1)][0].0.bus_time, 0.0);
+    assert_eq!(tree[&StopId(2)][0].0.bus_time, 600.0);
+
+    let searcher = TransitSearchProblem {
+        stops: &stops,
+        connections: &connections,
+        index: &index,
+        end: Position::Custom(end.0, end.1),
+        precache: HashMap::default(),
+        cost_model: model,
+        walks: None,
+    };
+    let from_stops = stops.values().map(|stop| (stop.stop_x, stop.stop_y));
+    let between = (0..32).map(|i| (i as f64 * 0.25 - 0.5, (i % 3) as f64 * 0.2));
+    for start in from_stops.chain(between) {
+        let (_, expected) = astar::astar(&searcher, Position::Custom(start.0, start.1)).unwrap();
+        let found = best_to_tree(&index, &model, &tree, start, end);
+        assert!((found.generalized - expected.generalized).abs() < 1.0, "from {:?}", start);
+        assert!((found.walk_time - expected.walk_time).abs() < 1.0, "from {:?}", start);
+    }
+}
+
+#[test]
+fn transfer_limits_keep_other_ways_open() {
+    // Changing at 2 gets to 3 sooner, but with one transfer allowed, only the
+    // slow bus leaves one for getting on to 4.
+    let (stops, connections, index) = branches();
+    let model = CostModel {
+        max_transfers: Some(1),
+        ..CostModel::default()
+    };
+    let (start, end) = ((0.0, 0.0), (15.0, 0.0));
+    let searcher = TransitSearchProblem {
+        stops: &stops,
+        connections: &connections,
+        index: &index,
+        end: Position::Custom(end.0, end.1),
+        precache: HashMap::default(),
+        cost_model: model,
+        walks: None,
+    };
+
+    let (_, cost) = astar::astar(&searcher, Position::Custom(start.0, start.1)).unwrap();
+    assert_eq!((cost.bus_time, cost.walk_time, cost.transfers), (3000.0, 0.0, 1));
+    let forward = search_all(&stops, &index, &connections, &model, start, 2.0 * 60.0 * 60.0);
+    assert_eq!(forward[&StopId(4)], cost);
+    let backward = search_to(&stops, &index, &connections, &model, end);
+    assert_eq!(backward[&StopId(1)][0].0.generalized, cost.generalized);
+}
+
+#[test]
+fn heuristic_allows_for_cheap_walking() {
+    // Walking the 10 km from stop 1 counts for less than riding there would,
+    // so guessing at driving over-estimates.
+    let (stops, connections, index) = line();
+    let model = CostModel {
+        walk_weight: 0.05,
+        ..CostModel::default()
+    };
+    let searcher = TransitSearchProblem {
+        stops: &stops,
+        connections: &connections,
+        index: &index,
+        end: Position::Custom(10.0, 0.0),
+        precache: HashMap::default(),
+        cost_model: model,
+        walks: None,
+    };
+    for id in stops.keys() {
+        let at = Position::BusStop(*id, HowGet::Walk, 0);
+        let (_, exact) = astar::astar(&Unguided(&searcher), at).unwrap();
+        assert!(searcher.heuristic(&at) <= exact);
+        assert_eq!(astar::astar(&searcher, at).unwrap().1, exact);
+    }
+}
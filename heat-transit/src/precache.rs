@@ -1,41 +1,175 @@
 use astar::astar;
-use fnv::FnvHashMap as HashMap;
+use cache;
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
 use model::*;
 use pathing::*;
-use serde_json::{from_reader, to_writer_pretty};
-use std::error::Error;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use time::TimeCost;
+use gtfs::FeedError;
+use progress::Progress;
+use rayon;
+use rayon::prelude::*;
+use spatial::StopIndex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use streets::StreetWalks;
+use time::{CostModel, TimeCost};
 
 pub type RouteCache = HashMap<StopId, TimeCost>;
 
-fn build_cache(stops: &Stops, connections: &PreConnections, destination: Position) -> RouteCache {
-    let mut searcher = TransitSearchProblem {
-        stops: &stops,
-        connections: &connections,
+// How many stops each thread searches from before what's been found so far is
+// saved, and used as the heuristic for the rest.
+const BATCH_PER_THREAD: usize = 16;
+
+/// Everything a route cache depends on. A cache built for anything else would
+/// make a wildly wrong heuristic.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct PrecacheMeta {
+    feed: FeedMeta,
+    // Coordinates of the destination in km
+    destination: (f64, f64),
+    walking_speed: f64,
+    max_walk_time: f32,
+    cost_model: CostModel,
+    // Hash of the street network walked along, if any
+    streets: Option<u64>,
+}
+
+fn meta(
+    options: &FeedOptions,
+    stops: &Stops,
+    destination: Position,
+    cost_model: &CostModel,
+    walks: Option<&StreetWalks>,
+) -> Result<PrecacheMeta, FeedError> {
+    Ok(PrecacheMeta {
+        feed: options.meta()?,
+        destination: destination.get_coords(stops),
+        walking_speed: WALKING_SPEED,
+        max_walk_time: MAX_WALK_TIME,
+        cost_model: *cost_model,
+        streets: walks.map(|walks| walks.streets().hash),
+    })
+}
+
+fn cache_path(options: &FeedOptions, meta: &PrecacheMeta) -> PathBuf {
+    options.cache_file(&format!("precache-{}.bin", cache::key(meta)))
+}
+
+// Where a cache is saved while it's being built, as the cost from every stop
+// searched so far and the stops the destination can't be reached from.
+fn partial_path(options: &FeedOptions, meta: &PrecacheMeta) -> PathBuf {
+    options.cache_file(&format!("precache-{}.partial", cache::key(meta)))
+}
+
+fn searcher<'a>(
+    stops: &'a Stops,
+    index: &'a StopIndex,
+    connections: &'a PreConnections,
+    destination: Position,
+    cost_model: &CostModel,
+    walks: Option<&'a StreetWalks<'a>>,
+) -> TransitSearchProblem<'a> {
+    TransitSearchProblem {
+        stops: stops,
+        connections: connections,
+        index: index,
         end: destination,
         precache: HashMap::default(),
-    };
-
-    let total = stops.len();
-    for (i, (id, _)) in stops.iter().enumerate() {
-        let start = Position::BusStop(*id, HowGet::Walk);
-        let (_, total_cost) = astar(&mut searcher, start).unwrap();
-        searcher.precache.insert(*id, total_cost);
-        println!("{} / {}", i, total);
+        cost_model: *cost_model,
+        walks: walks,
     }
+}
 
-    let file = BufWriter::new(File::create("cache/precache.json").unwrap());
-    to_writer_pretty(file, &searcher.precache).unwrap();
-    searcher.precache
+/// Searches from every stop in parallel, a batch at a time, filling in the
+/// precache of `searcher`. If an earlier build for the same `meta` was stopped
+/// partway, this carries on from its last batch.
+fn build_cache(
+    mut searcher: TransitSearchProblem,
+    partial: &Path,
+    meta: &PrecacheMeta,
+) -> Result<RouteCache, FeedError> {
+    // Anything wrong with what was saved just means starting over.
+    let (precache, mut unreachable): (RouteCache, HashSet<StopId>) =
+        cache::read(partial, meta).unwrap_or_default();
+    searcher.precache = precache;
+
+    let stops = searcher.stops;
+    let remaining: Vec<StopId> = stops
+        .keys()
+        .filter(|id| !searcher.precache.contains_key(id) && !unreachable.contains(id))
+        .cloned()
+        .collect();
+    let done = stops.len() - remaining.len();
+    if done > 0 {
+        println!("resuming from {} of {} stops", done, stops.len());
+    }
+    let progress = Progress::resume("Precaching stops", stops.len(), done);
+    for batch in remaining.chunks(rayon::current_num_threads() * BATCH_PER_THREAD) {
+        let found: Vec<(StopId, Option<TimeCost>)> = {
+            let searcher = &searcher;
+            let progress = &progress;
+            batch
+                .par_iter()
+                .map(|&id| {
+                    let start = Position::BusStop(id, HowGet::Walk, 0);
+                    let cost = astar(searcher, start).map(|(_, cost)| cost);
+                    progress.add(1);
+                    (id, cost)
+                })
+                .collect()
+        };
+        for (id, cost) in found {
+            match cost {
+                Some(cost) => {
+                    searcher.precache.insert(id, cost);
+                }
+                None => {
+                    unreachable.insert(id);
+                }
+            }
+        }
+        cache::write(partial, meta, &(&searcher.precache, &unreachable))?;
+    }
+    progress.finish();
+
+    if !unreachable.is_empty() {
+        eprintln!("warning: the destination can't be reached from {} stops", unreachable.len());
+    }
+    // The finished cache replaces it, so there's nothing to resume if this fails.
+    let _ = fs::remove_file(partial);
+    Ok(searcher.precache)
 }
 
-fn read_cache() -> Result<RouteCache, Box<Error>> {
-    let file = BufReader::new(File::open("cache/precache.json")?);
-    Ok(from_reader(file)?)
+/// Rebuilds the route cache for `destination`, regardless of what's cached.
+pub fn rebuild_cache(
+    options: &FeedOptions,
+    stops: &Stops,
+    index: &StopIndex,
+    connections: &PreConnections,
+    destination: Position,
+    cost_model: &CostModel,
+    walks: Option<&StreetWalks>,
+) -> Result<RouteCache, FeedError> {
+    let meta = meta(options, stops, destination, cost_model, walks)?;
+    let partial = partial_path(options, &meta);
+    let searcher = searcher(stops, index, connections, destination, cost_model, walks);
+    let precache = build_cache(searcher, &partial, &meta)?;
+    cache::write(&cache_path(options, &meta), &meta, &precache)?;
+    Ok(precache)
 }
 
-pub fn get_cache(stops: &Stops, connections: &PreConnections, destination: Position) -> RouteCache {
-    read_cache().unwrap_or_else(|_| build_cache(stops, connections, destination))
+pub fn get_cache(
+    options: &FeedOptions,
+    stops: &Stops,
+    index: &StopIndex,
+    connections: &PreConnections,
+    destination: Position,
+    cost_model: &CostModel,
+    walks: Option<&StreetWalks>,
+) -> Result<RouteCache, FeedError> {
+    let meta = meta(options, stops, destination, cost_model, walks)?;
+    let partial = partial_path(options, &meta);
+    cache::get_or_build(&cache_path(options, &meta), &meta, || {
+        let searcher = searcher(stops, index, connections, destination, cost_model, walks);
+        build_cache(searcher, &partial, &meta)
+    })
 }
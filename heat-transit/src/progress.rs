@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often the progress line gets redrawn.
+const REPORT_EVERY: Duration = Duration::from_millis(500);
+
+/// Reports how much of a long job is done, and about how long the rest will
+/// take, on one line of stderr. Work can be counted from any thread.
+pub struct Progress {
+    label: String,
+    total: usize,
+    done: AtomicUsize,
+    // Work done before this run, which doesn't count towards the rate
+    resumed: usize,
+    started: Instant,
+    last_report: Mutex<Option<Instant>>,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: usize) -> Self {
+        Progress::resume(label, total, 0)
+    }
+
+    /// Like `new`, for a job where `done` of the `total` was already done.
+    pub fn resume(label: &str, total: usize, done: usize) -> Self {
+        Progress {
+            label: label.to_string(),
+            total: total,
+            done: AtomicUsize::new(done),
+            resumed: done,
+            started: Instant::now(),
+            last_report: Mutex::new(None),
+        }
+    }
+
+    /// Counts `n` more done, redrawing the line if it's been long enough.
+    pub fn add(&self, n: usize) {
+        let done = self.done.fetch_add(n, Ordering::Relaxed) + n;
+        let now = Instant::now();
+        if let Ok(mut last) = self.last_report.try_lock() {
+            if last.is_none_or(|last| now.duration_since(last) >= REPORT_EVERY) {
+                *last = Some(now);
+                self.report(done, now);
+            }
+        }
+    }
+
+    /// Draws the final count and ends the line.
+    pub fn finish(&self) {
+        self.report(self.done.load(Ordering::Relaxed), Instant::now());
+        eprintln!();
+    }
+
+    fn report(&self, done: usize, now: Instant) {
+        let elapsed = now.duration_since(self.started);
+        let mut line = format!("\r{}: {} / {}", self.label, done, self.total);
+        if let Some(percent) = (done * 100).checked_div(self.total) {
+            line += &format!(" ({}%)", percent);
+        }
+        let rate = (done - self.resumed) as f64 / seconds(elapsed);
+        if done < self.total && rate > 0.0 {
+            let left = (self.total - done) as f64 / rate;
+            line += &format!(", about {} left", format_duration(left));
+        } else {
+            line += &format!(", took {}", format_duration(seconds(elapsed)));
+        }
+        // Blank out whatever was left of a longer line before.
+        eprint!("{:<60}", line);
+        let _ = io::stderr().flush();
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+/// Formats seconds as e.g. "1h 05m", "4m 30s" or "12s".
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 60 * 60 {
+        format!("{}h {:02}m", seconds / (60 * 60), seconds / 60 % 60)
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
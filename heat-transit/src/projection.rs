@@ -0,0 +1,150 @@
+use std::f64::consts::PI;
+
+// Mean radius of the earth in km
+const EARTH_RADIUS: f64 = 6371.0088;
+
+fn radians(degrees: f64) -> f64 {
+    degrees * (PI / 180.0)
+}
+
+fn degrees(radians: f64) -> f64 {
+    radians * (180.0 / PI)
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum ProjectionKind {
+    // Accurate to a few meters within a few hundred km of the origin
+    TransverseMercator,
+    // Treats degrees as evenly spaced, scaled for the origin's latitude. Only
+    // good for a city or so around the origin.
+    Equirectangular,
+}
+
+/// Converts latitude and longitude to km east and north of an origin, and back.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Projection {
+    pub kind: ProjectionKind,
+    // Latitude and longitude of (0, 0)
+    pub origin: (f64, f64),
+}
+
+impl Projection {
+    pub fn new(kind: ProjectionKind, origin: (f64, f64)) -> Self {
+        Projection {
+            kind: kind,
+            origin: origin,
+        }
+    }
+
+    /// A projection centered on the average of `points`, given as latitude and
+    /// longitude.
+    pub fn around<I: IntoIterator<Item = (f64, f64)>>(kind: ProjectionKind, points: I) -> Self {
+        let (mut lat, mut lon, mut count) = (0.0, 0.0, 0.0);
+        for (point_lat, point_lon) in points {
+            lat += point_lat;
+            lon += point_lon;
+            count += 1.0;
+        }
+        if count == 0.0 {
+            return Projection::new(kind, (0.0, 0.0));
+        }
+        Projection::new(kind, (lat / count, lon / count))
+    }
+
+    pub fn to_x_y(self, lat: f64, lon: f64) -> (f64, f64) {
+        let origin_lat = radians(self.origin.0);
+        // East of the origin's meridian the short way round, even across 180
+        // degrees.
+        let lon = radians((lon - self.origin.1 + 540.0) % 360.0 - 180.0);
+        let lat = radians(lat);
+        match self.kind {
+            ProjectionKind::TransverseMercator => {
+                // Spherical transverse Mercator, with the central meridian
+                // through the origin.
+                let b = lat.cos() * lon.sin();
+                let x = EARTH_RADIUS * b.atanh();
+                let y = EARTH_RADIUS * (lat.tan().atan2(lon.cos()) - origin_lat);
+                (x, y)
+            }
+            ProjectionKind::Equirectangular => {
+                let x = EARTH_RADIUS * lon * origin_lat.cos();
+                let y = EARTH_RADIUS * (lat - origin_lat);
+                (x, y)
+            }
+        }
+    }
+
+    /// The inverse of `to_x_y`.
+    pub fn to_lat_lon(self, x: f64, y: f64) -> (f64, f64) {
+        let (origin_lat, origin_lon) = (radians(self.origin.0), radians(self.origin.1));
+        let (lat, lon) = match self.kind {
+            ProjectionKind::TransverseMercator => {
+                let d = y / EARTH_RADIUS + origin_lat;
+                let lat = (d.sin() / (x / EARTH_RADIUS).cosh()).asin();
+                let lon = (x / EARTH_RADIUS).sinh().atan2(d.cos());
+                (lat, lon)
+            }
+            ProjectionKind::Equirectangular => {
+                let lat = y / EARTH_RADIUS + origin_lat;
+                let lon = x / (EARTH_RADIUS * origin_lat.cos());
+                (lat, lon)
+            }
+        };
+        // Keep longitudes within -180 to 180 degrees.
+        let lon = (degrees(lon + origin_lon) + 540.0) % 360.0 - 180.0;
+        (degrees(lat), lon)
+    }
+}
+
+/// Great circle distance in km between two points given as latitude and
+/// longitude.
+pub fn haversine((a_lat, a_lon): (f64, f64), (b_lat, b_lon): (f64, f64)) -> f64 {
+    let (a_lat, b_lat) = (radians(a_lat), radians(b_lat));
+    let d_lat = b_lat - a_lat;
+    let d_lon = radians(b_lon - a_lon);
+    let h = (d_lat / 2.0).sin().powi(2) + a_lat.cos() * b_lat.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+#[test]
+fn projects_there_and_back() {
+    let seattle = (47.6, -122.33);
+    for &kind in &[ProjectionKind::TransverseMercator, ProjectionKind::Equirectangular] {
+        let projection = Projection::new(kind, seattle);
+        let (x, y) = projection.to_x_y(seattle.0, seattle.1);
+        assert!(x.abs() < 1e-9 && y.abs() < 1e-9);
+        // Downtown, Everett and Tacoma
+        let places = [(47.61, -122.34), (47.98, -122.2), (47.25, -122.44)];
+        for &(lat, lon) in &places {
+            let (x, y) = projection.to_x_y(lat, lon);
+            let (back_lat, back_lon) = projection.to_lat_lon(x, y);
+            assert!((back_lat - lat).abs() < 1e-9, "{:?}: {} != {}", kind, back_lat, lat);
+            assert!((back_lon - lon).abs() < 1e-9, "{:?}: {} != {}", kind, back_lon, lon);
+        }
+        // Longitudes wrap around the antimeridian.
+        let fiji = Projection::new(kind, (-17.7, 179.9));
+        let (x, y) = fiji.to_x_y(-17.6, -179.8);
+        assert!(x > 0.0 && x < 50.0);
+        let (lat, lon) = fiji.to_lat_lon(x, y);
+        assert!((lat + 17.6).abs() < 1e-9 && (lon + 179.8).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn haversine_matches_known_distances() {
+    let close = |km: f64, expected: f64| (km - expected).abs() < 0.01;
+    // A degree along a meridian, a quarter of the equator, and London to Paris
+    assert!(close(haversine((0.0, 0.0), (1.0, 0.0)), 111.195));
+    assert!(close(haversine((0.0, 0.0), (0.0, 90.0)), 10_007.557));
+    assert!(close(haversine((51.5074, -0.1278), (48.8566, 2.3522)), 343.557));
+    assert_eq!(haversine((47.6, -122.33), (47.6, -122.33)), 0.0);
+
+    // Within a city, the transverse Mercator projection keeps distances to
+    // within a few meters.
+    let projection = Projection::new(ProjectionKind::TransverseMercator, (47.6, -122.33));
+    let (a, b) = ((47.7, -122.2), (47.5, -122.45));
+    let (ax, ay) = projection.to_x_y(a.0, a.1);
+    let (bx, by) = projection.to_x_y(b.0, b.1);
+    let flat = ((ax - bx).powi(2) + (ay - by).powi(2)).sqrt();
+    assert!((flat - haversine(a, b)).abs() < 0.005);
+}
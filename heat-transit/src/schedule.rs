@@ -0,0 +1,363 @@
+use fnv::FnvHashMap as HashMap;
+use model::{PreConnections, StopId, Stops};
+use pathing::{walking_time, HowGet, Position, MAX_WALK_DISTANCE, MAX_WALK_TIME, WALKING_SPEED};
+use spatial::StopIndex;
+use std::cmp::Ordering;
+use time::TimeCost;
+
+// Longest walk between two stops that we consider a transfer.
+const MAX_TRANSFER_TIME: f32 = 5.0 * 60.0;
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TimedConnection {
+    pub from: StopId,
+    pub to: StopId,
+    // Seconds since the start of the service day
+    pub departure: u64,
+    pub arrival: u64,
+    pub trip_id: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Timetable {
+    // Sorted by departure time
+    pub connections: Vec<TimedConnection>,
+}
+
+impl Timetable {
+    /// Index of the first connection departing at or after `time`.
+    fn first_departing(&self, time: u64) -> usize {
+        self.connections
+            .binary_search_by(|c| {
+                if c.departure < time {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|i| i)
+    }
+
+    /// Every trip that can be boarded between `from` and `until` seconds, as
+    /// route patterns like `model::get_connections` gives. A trip counts from
+    /// the first stop it leaves in the window, and headways are the window
+    /// split evenly between the trips.
+    pub fn connections_between(&self, from: u64, until: u64) -> PreConnections {
+        // Hops of every trip running in the window, from the first one that
+        // departs in it to the end of the trip.
+        let mut trips: HashMap<u32, Vec<&TimedConnection>> = HashMap::default();
+        for c in &self.connections[self.first_departing(from)..] {
+            if c.departure < until {
+                trips.entry(c.trip_id).or_default().push(c);
+            } else if let Some(hops) = trips.get_mut(&c.trip_id) {
+                hops.push(c);
+            }
+        }
+
+        let trips = trips.into_iter().map(|(trip_id, hops)| {
+            let mut stops = vec![(hops[0].from, hops[0].departure)];
+            stops.extend(hops.iter().map(|hop| (hop.to, hop.arrival)));
+            (trip_id, 1.0, stops)
+        });
+        PreConnections::from_trips(trips).in_window(Some((from, until)))
+    }
+}
+
+type Footpaths = HashMap<StopId, Vec<(StopId, TimeCost)>>;
+
+/// Earliest-arrival routing over the scheduled departures, using the
+/// connection scan algorithm.
+pub struct ScheduleSearch<'a> {
+    stops: &'a Stops,
+    index: &'a StopIndex,
+    timetable: &'a Timetable,
+    footpaths: Footpaths,
+}
+
+/// One part of the journey `ScheduleSearch::journey` finds.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Hop<'a> {
+    Walk(Position, Position),
+    // Riding one trip, from the connection boarded to the one got off
+    Ride(&'a TimedConnection, &'a TimedConnection),
+}
+
+// How the scan last got to a stop, to follow back from the end. Each stop's
+// label only ever gets earlier, so following these back always makes a
+// journey that can be taken, and that gets to the end just as early.
+#[derive(Clone, Copy, Debug)]
+enum Reached {
+    // From another stop, or from the start
+    Walk(Option<StopId>),
+    // Indices of the connections boarded and got off in the timetable
+    Ride(usize, usize),
+}
+
+// The fastest way to the end, and the stop its last walk leaves from, if it
+// doesn't walk the whole way.
+type Best = (TimeCost, Option<StopId>);
+
+fn improve(best: &mut Best, cost: TimeCost, from: StopId) {
+    if cost < best.0 {
+        *best = (cost, Some(from));
+    }
+}
+
+fn build_footpaths(stops: &Stops, index: &StopIndex) -> Footpaths {
+    let max_distance = MAX_TRANSFER_TIME as f64 * WALKING_SPEED;
+    let mut footpaths = HashMap::default();
+    for (id, stop) in stops {
+        let here = (stop.stop_x, stop.stop_y);
+        let nearby = index
+            .near(here, max_distance)
+            .filter(|&(other, _)| other != *id)
+            .map(|(other, coords)| (other, walking_time(here, coords)))
+            .filter(|&(_, walk)| walk.walk_time < MAX_TRANSFER_TIME)
+            .collect();
+        footpaths.insert(*id, nearby);
+    }
+    footpaths
+}
+
+impl<'a> ScheduleSearch<'a> {
+    pub fn new(stops: &'a Stops, index: &'a StopIndex, timetable: &'a Timetable) -> Self {
+        ScheduleSearch {
+            stops: stops,
+            index: index,
+            timetable: timetable,
+            footpaths: build_footpaths(stops, index),
+        }
+    }
+
+    /// Leaving `start` at `departure` seconds into the service day, find the
+    /// fastest way to get to `end`, waiting at stops for the next scheduled bus.
+    pub fn earliest_arrival(&self, start: Position, departure: u64, end: Position) -> TimeCost {
+        let ((best, _), _) = self.scan(start, departure, end);
+        best
+    }
+
+    /// Like `earliest_arrival`, but also gives the walks and rides that get
+    /// there, from `start` to `end`.
+    pub fn journey(
+        &self,
+        start: Position,
+        departure: u64,
+        end: Position,
+    ) -> (Vec<Hop<'a>>, TimeCost) {
+        let ((best, last), reached) = self.scan(start, departure, end);
+        let stop = |id: StopId| Position::BusStop(id, HowGet::Walk, 0);
+        let connections = &self.timetable.connections;
+
+        let mut at = match last {
+            Some(id) => id,
+            None => return (vec![Hop::Walk(start, end)], best),
+        };
+        let mut hops = vec![Hop::Walk(stop(at), end)];
+        loop {
+            match reached[&at] {
+                Reached::Walk(None) => {
+                    hops.push(Hop::Walk(start, stop(at)));
+                    break;
+                }
+                Reached::Walk(Some(from)) => {
+                    hops.push(Hop::Walk(stop(from), stop(at)));
+                    at = from;
+                }
+                Reached::Ride(boarded, got_off) => {
+                    let boarded = &connections[boarded];
+                    hops.push(Hop::Ride(boarded, &connections[got_off]));
+                    at = boarded.from;
+                }
+            }
+        }
+        hops.reverse();
+        (hops, best)
+    }
+
+    fn scan(
+        &self,
+        start: Position,
+        departure: u64,
+        end: Position,
+    ) -> (Best, HashMap<StopId, Reached>) {
+        let start = start.get_coords(self.stops);
+        let end = end.get_coords(self.stops);
+
+        let walk_to_end = |id: &StopId| {
+            let stop = &self.stops[id];
+            let walk = walking_time((stop.stop_x, stop.stop_y), end);
+            if walk.walk_time < MAX_WALK_TIME {
+                Some(walk)
+            } else {
+                None
+            }
+        };
+
+        // Walking the whole way is always an option.
+        let mut best: Best = (walking_time(start, end), None);
+        let mut labels: HashMap<StopId, TimeCost> = HashMap::default();
+        let mut reached: HashMap<StopId, Reached> = HashMap::default();
+        for (id, coords) in self.index.near(start, MAX_WALK_DISTANCE) {
+            let walk = walking_time(start, coords);
+            if walk.walk_time < MAX_WALK_TIME {
+                labels.insert(id, walk);
+                reached.insert(id, Reached::Walk(None));
+                if let Some(rest) = walk_to_end(&id) {
+                    improve(&mut best, walk + rest, id);
+                }
+            }
+        }
+
+        // For every trip we have boarded: the cost when boarding, and the
+        // connection it was boarded at.
+        let mut trips: HashMap<u32, (TimeCost, usize)> = HashMap::default();
+
+        let connections = &self.timetable.connections;
+        let first = self.timetable.first_departing(departure);
+        for (i, c) in connections.iter().enumerate().skip(first) {
+            if c.departure as f64 >= departure as f64 + best.0.total() as f64 {
+                break;
+            }
+
+            let (boarded, boarded_at) = match trips.get(&c.trip_id) {
+                Some(&trip) => trip,
+                None => {
+                    let label = match labels.get(&c.from) {
+                        Some(&label) => label,
+                        None => continue,
+                    };
+                    let ready_at = departure as f64 + label.total() as f64;
+                    if ready_at > c.departure as f64 {
+                        continue;
+                    }
+                    let wait = TimeCost::of_waiting((c.departure as f64 - ready_at) as f32);
+                    let mut boarded = label + wait;
+                    if label.has_boarded() {
+                        boarded = boarded + TimeCost::of_transfer();
+                    }
+                    trips.insert(c.trip_id, (boarded, i));
+                    (boarded, i)
+                }
+            };
+            let ride = c.arrival - connections[boarded_at].departure;
+            let arrived = boarded + TimeCost::of_bus(ride as f32);
+            if labels.get(&c.to).is_some_and(|&l| l <= arrived) {
+                continue;
+            }
+
+            labels.insert(c.to, arrived);
+            reached.insert(c.to, Reached::Ride(boarded_at, i));
+            if let Some(rest) = walk_to_end(&c.to) {
+                improve(&mut best, arrived + rest, c.to);
+            }
+
+            for &(other, walk) in &self.footpaths[&c.to] {
+                let transferred = arrived + walk;
+                if labels.get(&other).is_some_and(|&l| l <= transferred) {
+                    continue;
+                }
+                labels.insert(other, transferred);
+                reached.insert(other, Reached::Walk(Some(c.to)));
+                if let Some(rest) = walk_to_end(&other) {
+                    improve(&mut best, transferred + rest, other);
+                }
+            }
+        }
+
+        (best, reached)
+    }
+}
+
+#[cfg(test)]
+fn timetable() -> (Stops, Timetable, StopIndex) {
+    use model::Stop;
+    // A line from stop 1 to stop 2, 5 km east, and another from stop 3, a
+    // couple of minutes' walk past stop 2, to stop 4, 5 km further. Buses
+    // leave stop 1 at 8:00 and 8:10 and stop 3 at 8:15 and 9:00, each taking
+    // ten minutes.
+    let mut stops = HashMap::default();
+    for (i, &x) in [0.0, 5.0, 5.2, 10.2].iter().enumerate() {
+        let id = StopId(i as u32 + 1);
+        let stop = Stop {
+            stop_id: id,
+            stop_x: x,
+            stop_y: 0.0,
+            name: format!("Stop {}", i + 1),
+        };
+        stops.insert(id, stop);
+    }
+    let hop = |trip_id, from, to, departure| TimedConnection {
+        from: StopId(from),
+        to: StopId(to),
+        departure: departure,
+        arrival: departure + 600,
+        trip_id: trip_id,
+    };
+    let timetable = Timetable {
+        connections: vec![
+            hop(1, 1, 2, 28_800),
+            hop(2, 1, 2, 29_400),
+            hop(3, 3, 4, 29_700),
+            hop(4, 3, 4, 32_400),
+        ],
+    };
+    let index = StopIndex::new(&stops, MAX_WALK_DISTANCE);
+    (stops, timetable, index)
+}
+
+#[test]
+fn transfers_between_lines() {
+    let (stops, timetable, index) = timetable();
+    let searcher = ScheduleSearch::new(&stops, &index, &timetable);
+    let (start, end) = (Position::Custom(0.0, 0.0), Position::Custom(10.2, 0.0));
+    let cost = searcher.earliest_arrival(start, 28_800, end);
+    assert_eq!((cost.bus_time, cost.transfers), (1200.0, 1));
+    // Arriving at 8:25
+    assert!((cost.total() - 1500.0).abs() < 0.1);
+}
+
+#[test]
+fn waits_after_a_missed_connection() {
+    let (stops, timetable, index) = timetable();
+    let searcher = ScheduleSearch::new(&stops, &index, &timetable);
+    let (start, end) = (Position::Custom(0.0, 0.0), Position::Custom(10.2, 0.0));
+    // Just missing the 8:00 means getting to stop 3 after the 8:15 has left,
+    // so arriving at 9:10.
+    let cost = searcher.earliest_arrival(start, 28_801, end);
+    assert_eq!((cost.bus_time, cost.transfers), (1200.0, 1));
+    assert!((cost.total() - (33_000.0 - 28_801.0)).abs() < 0.1);
+}
+
+#[test]
+fn walks_after_the_last_trip() {
+    let (stops, timetable, index) = timetable();
+    let searcher = ScheduleSearch::new(&stops, &index, &timetable);
+    let (start, end) = (Position::Custom(0.0, 0.0), Position::Custom(10.2, 0.0));
+    // Just after the last bus, at 9:00, walking is all that's left.
+    let cost = searcher.earliest_arrival(start, 32_401, end);
+    assert_eq!((cost.bus_time, cost.wait_time), (0.0, 0.0));
+    assert_eq!(cost, walking_time((0.0, 0.0), (10.2, 0.0)));
+}
+
+#[test]
+fn follows_the_journey_back() {
+    let (stops, timetable, index) = timetable();
+    let searcher = ScheduleSearch::new(&stops, &index, &timetable);
+    let (start, end) = (Position::Custom(0.0, 0.0), Position::Custom(10.2, 0.0));
+    let stop = |id| Position::BusStop(StopId(id), HowGet::Walk, 0);
+    let (hops, cost) = searcher.journey(start, 28_800, end);
+    assert_eq!(cost, searcher.earliest_arrival(start, 28_800, end));
+    assert_eq!(
+        hops,
+        vec![
+            Hop::Walk(start, stop(1)),
+            Hop::Ride(&timetable.connections[0], &timetable.connections[0]),
+            Hop::Walk(stop(2), stop(3)),
+            Hop::Ride(&timetable.connections[2], &timetable.connections[2]),
+            Hop::Walk(stop(4), end),
+        ]
+    );
+
+    let (hops, _) = searcher.journey(start, 32_401, end);
+    assert_eq!(hops, vec![Hop::Walk(start, end)]);
+}
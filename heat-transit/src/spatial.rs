@@ -0,0 +1,54 @@
+use fnv::FnvHashMap as HashMap;
+use model::{StopId, Stops};
+
+// The stops in each cell, with their coordinates
+type Cells = HashMap<(i64, i64), Vec<(StopId, (f64, f64))>>;
+
+/// Buckets stops into a grid of square cells so that finding the stops near
+/// a point only has to look at the cells around it.
+pub struct StopIndex {
+    // Width of a cell in km
+    cell_size: f64,
+    cells: Cells,
+}
+
+impl StopIndex {
+    /// `cell_size` is best set to the radius most queries will use.
+    pub fn new(stops: &Stops, cell_size: f64) -> Self {
+        let mut index = StopIndex {
+            cell_size: cell_size,
+            cells: HashMap::default(),
+        };
+        for (id, stop) in stops {
+            let coords = (stop.stop_x, stop.stop_y);
+            let cell = index.cell(coords);
+            index.cells.entry(cell).or_default().push((*id, coords));
+        }
+        index
+    }
+
+    fn cell(&self, (x, y): (f64, f64)) -> (i64, i64) {
+        (
+            (x / self.cell_size).floor() as i64,
+            (y / self.cell_size).floor() as i64,
+        )
+    }
+
+    /// Every stop within `radius` km of `center`, along with its coordinates.
+    pub fn near<'a>(
+        &'a self,
+        center: (f64, f64),
+        radius: f64,
+    ) -> impl Iterator<Item = (StopId, (f64, f64))> + 'a {
+        let (min_x, min_y) = self.cell((center.0 - radius, center.1 - radius));
+        let (max_x, max_y) = self.cell((center.0 + radius, center.1 + radius));
+        (min_x..max_x + 1)
+            .flat_map(move |cx| (min_y..max_y + 1).map(move |cy| (cx, cy)))
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flat_map(|stops| stops.iter().cloned())
+            .filter(move |&(_, (x, y))| {
+                let (dx, dy) = (x - center.0, y - center.1);
+                dx * dx + dy * dy <= radius * radius
+            })
+    }
+}
@@ -0,0 +1,166 @@
+use csv;
+use draw::RenderSettings;
+use num_traits::Zero;
+use projection::Projection;
+use serde_json::to_writer_pretty;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use time::TimeCost;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+struct RawBlock {
+    lat: f64,
+    lon: f64,
+    population: f64,
+}
+
+/// A census block, by the pixel its centroid falls in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub pixel: (u32, u32),
+    pub population: f64,
+}
+
+/// Reads a CSV of census block centroids with lat, lon and population columns,
+/// keeping the ones on the map.
+pub fn read_blocks(
+    path: &Path,
+    settings: &RenderSettings,
+    projection: &Projection,
+) -> Result<Vec<Block>, Box<Error>> {
+    let mut out = Vec::new();
+    for row in csv::Reader::from_path(path)?.into_deserialize() {
+        let raw: RawBlock = row?;
+        let (x, y) = projection.to_x_y(raw.lat, raw.lon);
+        if let Some(pixel) = settings.pixel_at(x, y) {
+            out.push(Block {
+                pixel: pixel,
+                population: raw.population,
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Everything with a travel time between `from_minutes` and `to_minutes`.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Bin {
+    pub from_minutes: f32,
+    pub to_minutes: f32,
+    pub area_km2: f64,
+    // Everything within `to_minutes`, this bin included
+    pub reachable_area_km2: f64,
+    // Average of each part of the trips in this bin
+    pub walk_minutes: f32,
+    pub bus_minutes: f32,
+    pub wait_minutes: f32,
+    // Only when census blocks are given
+    pub population: Option<f64>,
+    pub reachable_population: Option<f64>,
+}
+
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Report {
+    pub area_km2: f64,
+    pub unreachable_area_km2: f64,
+    pub population: Option<f64>,
+    pub bins: Vec<Bin>,
+}
+
+/// Sums up a grid from `draw::render` into bins `bin_minutes` wide.
+pub fn report(
+    settings: &RenderSettings,
+    grid: &[Option<TimeCost>],
+    blocks: Option<&[Block]>,
+    bin_minutes: f32,
+) -> Report {
+    let pixel_size = 2.0 * settings.extent / settings.size as f64;
+    let pixel_area = pixel_size * pixel_size;
+    let bin_of = |cost: &TimeCost| (cost.total() / 60.0 / bin_minutes).max(0.0) as usize;
+
+    let bin_count = grid.iter().flatten().map(|x| bin_of(x) + 1).max().unwrap_or(0);
+    let mut bins: Vec<Bin> = (0..bin_count)
+        .map(|i| Bin {
+            from_minutes: i as f32 * bin_minutes,
+            to_minutes: (i + 1) as f32 * bin_minutes,
+            area_km2: 0.0,
+            reachable_area_km2: 0.0,
+            walk_minutes: 0.0,
+            bus_minutes: 0.0,
+            wait_minutes: 0.0,
+            population: blocks.map(|_| 0.0),
+            reachable_population: blocks.map(|_| 0.0),
+        })
+        .collect();
+
+    let mut unreachable_area = 0.0;
+    let mut sums = vec![(0, TimeCost::zero()); bin_count];
+    for cost in grid {
+        match *cost {
+            Some(cost) => {
+                let sum = &mut sums[bin_of(&cost)];
+                sum.0 += 1;
+                sum.1 = sum.1 + cost;
+            }
+            None => unreachable_area += pixel_area,
+        }
+    }
+    for (bin, &(count, sum)) in bins.iter_mut().zip(&sums) {
+        bin.area_km2 = count as f64 * pixel_area;
+        if count > 0 {
+            bin.walk_minutes = sum.walk_time / 60.0 / count as f32;
+            bin.bus_minutes = sum.bus_time / 60.0 / count as f32;
+            bin.wait_minutes = sum.wait_time / 60.0 / count as f32;
+        }
+    }
+
+    let mut population = None;
+    if let Some(blocks) = blocks {
+        let size = settings.size;
+        for block in blocks {
+            let (x, y) = block.pixel;
+            if let Some(ref cost) = grid[(y * size + x) as usize] {
+                if let Some(ref mut p) = bins[bin_of(cost)].population {
+                    *p += block.population;
+                }
+            }
+        }
+        population = Some(blocks.iter().map(|b| b.population).sum());
+    }
+
+    let (mut area, mut people) = (0.0, 0.0);
+    for bin in &mut bins {
+        area += bin.area_km2;
+        bin.reachable_area_km2 = area;
+        if let Some(p) = bin.population {
+            people += p;
+            bin.reachable_population = Some(people);
+        }
+    }
+
+    Report {
+        area_km2: grid.len() as f64 * pixel_area,
+        unreachable_area_km2: unreachable_area,
+        population: population,
+        bins: bins,
+    }
+}
+
+impl Report {
+    /// Writes the report as CSV if `path` ends in .csv, where only the bins
+    /// fit, and as JSON otherwise.
+    pub fn write(&self, path: &Path) -> Result<(), Box<Error>> {
+        if path.extension().is_some_and(|e| e == "csv") {
+            let mut writer = csv::Writer::from_path(path)?;
+            for bin in &self.bins {
+                writer.serialize(bin)?;
+            }
+            writer.flush()?;
+        } else {
+            to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        }
+        Ok(())
+    }
+}
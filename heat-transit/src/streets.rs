@@ -0,0 +1,286 @@
+use cache;
+use fnv::FnvHashMap as HashMap;
+use model::{StopId, Stops};
+use pathing::{walking_time_for, MAX_WALK_DISTANCE, MAX_WALK_TIME};
+use projection::{haversine, Projection};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use time::TimeCost;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+// Places further than this from any street can't be walked to or from, in km.
+const SNAP_DISTANCE: f64 = 0.2;
+
+/// A pedestrian street network read from an OpenStreetMap XML extract, in the
+/// same km coordinates as stops.
+pub struct Streets {
+    nodes: Vec<(f64, f64)>,
+    // Neighbors of every node and how far away they are in km
+    edges: Vec<Vec<(usize, f64)>>,
+    // Nodes bucketed into SNAP_DISTANCE sized cells
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    // Hash of the extract, for keying caches built with it
+    pub hash: u64,
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.as_str())
+}
+
+/// Whether people can walk along a way with these tags.
+fn walkable(tags: &HashMap<String, String>) -> bool {
+    let tag = |k: &str| tags.get(k).map(|v| v.as_str());
+    match tag("foot") {
+        Some("yes") | Some("designated") | Some("permissive") => return true,
+        Some("no") => return false,
+        _ => {}
+    }
+    if let Some("no") | Some("private") = tag("access") {
+        return false;
+    }
+    match tag("highway") {
+        Some("motorway") | Some("motorway_link") | Some("trunk") | Some("trunk_link")
+        | Some("construction") | Some("proposed") | Some("raceway") | Some("bus_guideway") => {
+            false
+        }
+        Some(_) => true,
+        None => false,
+    }
+}
+
+fn distance((ax, ay): (f64, f64), (bx, by): (f64, f64)) -> f64 {
+    ((ax - bx) * (ax - bx) + (ay - by) * (ay - by)).sqrt()
+}
+
+// Orders the search frontier so that `BinaryHeap` pops the closest first.
+#[derive(PartialEq)]
+struct Visit(f64, usize);
+
+impl Eq for Visit {}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.partial_cmp(&self.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Streets {
+    pub fn load(path: &Path, projection: &Projection) -> Result<Streets, Box<Error>> {
+        // Latitude and longitude of every node
+        let mut coords: HashMap<i64, (f64, f64)> = HashMap::default();
+        let mut ways: Vec<Vec<i64>> = vec![];
+
+        // The way being read, if any: its nodes and tags.
+        let mut way: Option<(Vec<i64>, HashMap<String, String>)> = None;
+        for event in EventReader::new(BufReader::new(File::open(path)?)) {
+            match event? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => match name.local_name.as_str() {
+                    "node" => {
+                        let id = attribute(&attributes, "id");
+                        let lat = attribute(&attributes, "lat");
+                        let lon = attribute(&attributes, "lon");
+                        if let (Some(id), Some(lat), Some(lon)) = (id, lat, lon) {
+                            coords.insert(id.parse()?, (lat.parse()?, lon.parse()?));
+                        }
+                    }
+                    "way" => way = Some((vec![], HashMap::default())),
+                    "nd" => {
+                        let node = attribute(&attributes, "ref");
+                        if let (Some(way), Some(node)) = (way.as_mut(), node) {
+                            way.0.push(node.parse()?);
+                        }
+                    }
+                    "tag" => {
+                        let k = attribute(&attributes, "k");
+                        let v = attribute(&attributes, "v");
+                        if let (Some(way), Some(k), Some(v)) = (way.as_mut(), k, v) {
+                            way.1.insert(k.to_owned(), v.to_owned());
+                        }
+                    }
+                    _ => {}
+                },
+                XmlEvent::EndElement { name } if name.local_name == "way" => {
+                    if let Some((nodes, tags)) = way.take() {
+                        if walkable(&tags) {
+                            ways.push(nodes);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut streets = Streets {
+            nodes: vec![],
+            edges: vec![],
+            cells: HashMap::default(),
+            hash: cache::hash_path(path)?,
+        };
+        let mut indices: HashMap<i64, usize> = HashMap::default();
+        for way in ways {
+            let way: Vec<(usize, (f64, f64))> = way
+                .into_iter()
+                .filter_map(|id| {
+                    let &(lat, lon) = coords.get(&id)?;
+                    let next = streets.nodes.len();
+                    let index = *indices.entry(id).or_insert(next);
+                    if index == next {
+                        streets.nodes.push(projection.to_x_y(lat, lon));
+                        streets.edges.push(vec![]);
+                    }
+                    Some((index, (lat, lon)))
+                })
+                .collect();
+            for pair in way.windows(2) {
+                let ((a, a_lat_lon), (b, b_lat_lon)) = (pair[0], pair[1]);
+                let length = haversine(a_lat_lon, b_lat_lon);
+                streets.edges[a].push((b, length));
+                streets.edges[b].push((a, length));
+            }
+        }
+        for (i, &coords) in streets.nodes.iter().enumerate() {
+            let cell = Streets::cell(coords);
+            streets.cells.entry(cell).or_default().push(i);
+        }
+        Ok(streets)
+    }
+
+    fn cell((x, y): (f64, f64)) -> (i64, i64) {
+        ((x / SNAP_DISTANCE).floor() as i64, (y / SNAP_DISTANCE).floor() as i64)
+    }
+
+    /// The closest node to `point` and how far away it is, if it's within
+    /// SNAP_DISTANCE.
+    fn snap(&self, point: (f64, f64)) -> Option<(usize, f64)> {
+        let (cx, cy) = Streets::cell(point);
+        let mut best: Option<(usize, f64)> = None;
+        for x in cx - 1..cx + 2 {
+            for y in cy - 1..cy + 2 {
+                for &node in self.cells.get(&(x, y)).into_iter().flatten() {
+                    let d = distance(point, self.nodes[node]);
+                    if d <= SNAP_DISTANCE && best.is_none_or(|(_, b)| d < b) {
+                        best = Some((node, d));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Dijkstra from `point`, giving how far every node within `limit` km is.
+    fn distances(&self, point: (f64, f64), limit: f64) -> HashMap<usize, f64> {
+        let mut done = HashMap::default();
+        let mut frontier = BinaryHeap::new();
+        if let Some((node, d)) = self.snap(point) {
+            frontier.push(Visit(d, node));
+        }
+        while let Some(Visit(d, node)) = frontier.pop() {
+            if done.contains_key(&node) {
+                continue;
+            }
+            done.insert(node, d);
+            for &(next, length) in &self.edges[node] {
+                if d + length <= limit && !done.contains_key(&next) {
+                    frontier.push(Visit(d + length, next));
+                }
+            }
+        }
+        done
+    }
+
+    /// How long walking from `a` to `b` along the streets takes, if it's possible.
+    pub fn walk(&self, a: (f64, f64), b: (f64, f64)) -> Option<TimeCost> {
+        let (node, d) = self.snap(b)?;
+        let km = self.distances(a, f64::INFINITY).get(&node)? + d;
+        Some(walking_time_for(km))
+    }
+}
+
+/// Walking times along the streets for searches to one destination, with the
+/// walks between stops worked out ahead of time.
+pub struct StreetWalks<'a> {
+    streets: &'a Streets,
+    // Stops snapped to each node, and how far they are from it in km
+    stops_at: HashMap<usize, Vec<(StopId, f64)>>,
+    // km from each node to the destination
+    to_end: HashMap<usize, f64>,
+    footpaths: HashMap<StopId, Vec<(StopId, TimeCost)>>,
+}
+
+impl<'a> StreetWalks<'a> {
+    pub fn new(streets: &'a Streets, stops: &Stops, end: (f64, f64)) -> Self {
+        let mut walks = StreetWalks {
+            streets: streets,
+            stops_at: HashMap::default(),
+            to_end: streets.distances(end, f64::INFINITY),
+            footpaths: HashMap::default(),
+        };
+        for (id, stop) in stops {
+            if let Some((node, d)) = streets.snap((stop.stop_x, stop.stop_y)) {
+                walks.stops_at.entry(node).or_default().push((*id, d));
+            }
+        }
+
+        let stops: Vec<_> = stops.iter().collect();
+        let footpaths: Vec<_> = stops
+            .par_iter()
+            .map(|&(id, stop)| {
+                let nearby = walks
+                    .to_stops((stop.stop_x, stop.stop_y))
+                    .into_iter()
+                    .filter(|&(other, _)| other != *id)
+                    .collect();
+                (*id, nearby)
+            })
+            .collect();
+        walks.footpaths = footpaths.into_iter().collect();
+        walks
+    }
+
+    /// Every stop within MAX_WALK_TIME of `from`.
+    pub fn to_stops(&self, from: (f64, f64)) -> Vec<(StopId, TimeCost)> {
+        let mut out = vec![];
+        for (node, d) in self.streets.distances(from, MAX_WALK_DISTANCE) {
+            for &(id, snap) in self.stops_at.get(&node).into_iter().flatten() {
+                let walk = walking_time_for(d + snap);
+                if walk.walk_time < MAX_WALK_TIME {
+                    out.push((id, walk));
+                }
+            }
+        }
+        out
+    }
+
+    /// Every stop within MAX_WALK_TIME of the stop `id`.
+    pub fn footpaths(&self, id: StopId) -> &[(StopId, TimeCost)] {
+        self.footpaths.get(&id).map_or(&[], |f| &f[..])
+    }
+
+    /// How long walking from `from` to the destination takes, if it's possible.
+    pub fn to_end(&self, from: (f64, f64)) -> Option<TimeCost> {
+        let (node, d) = self.streets.snap(from)?;
+        let rest = self.to_end.get(&node)?;
+        Some(walking_time_for(d + rest))
+    }
+
+    pub fn streets(&self) -> &Streets {
+        self.streets
+    }
+}
@@ -1,4 +1,5 @@
 use std::cmp::{Ord, Ordering, PartialOrd};
+use std::fmt;
 
 #[derive(PartialEq, Deserialize, Serialize, Copy, Clone, Debug)]
 pub struct TimeCost {
@@ -6,6 +7,94 @@ pub struct TimeCost {
     pub bus_time: f32,
     pub wait_time: f32,
     pub transfers: u32,
+    // What searches minimize: the times above weighted by a `CostModel`, plus
+    // its transfer penalties. Without a model this is just `total()`.
+    pub generalized: f32,
+}
+
+/// How long riders expect to wait for a bus.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum WaitStrategy {
+    // The same wait for every bus, in seconds
+    Constant(f32),
+    // Half the time between departures, as for riders turning up at random
+    HalfHeadway,
+}
+
+impl WaitStrategy {
+    /// Seconds spent waiting for a bus that leaves every `headway` seconds.
+    pub fn expected_wait(&self, headway: f32) -> f32 {
+        match *self {
+            WaitStrategy::Constant(wait) => wait,
+            WaitStrategy::HalfHeadway => headway / 2.0,
+        }
+    }
+}
+
+/// How much riders mind each part of a trip, relative to a second on the bus.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CostModel {
+    pub walk_weight: f32,
+    pub ride_weight: f32,
+    pub wait_weight: f32,
+    // Seconds added for every transfer
+    pub transfer_penalty: f32,
+    pub max_transfers: Option<u32>,
+    pub wait: WaitStrategy,
+    // Only buses leaving between these two times, in seconds since the start
+    // of the service day, count towards headways. The whole day if not set.
+    // Connections are loaded for it, rather than searches reading it.
+    pub window: Option<(u64, u64)>,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            walk_weight: 1.0,
+            ride_weight: 1.0,
+            wait_weight: 1.0,
+            transfer_penalty: 0.0,
+            max_transfers: None,
+            wait: WaitStrategy::HalfHeadway,
+            window: None,
+        }
+    }
+}
+
+impl CostModel {
+    /// Recomputes the generalized cost of `cost` under this model.
+    pub fn weigh(&self, cost: TimeCost) -> TimeCost {
+        TimeCost {
+            generalized: cost.walk_time * self.walk_weight
+                + cost.bus_time * self.ride_weight
+                + cost.wait_time * self.wait_weight
+                + cost.transfers as f32 * self.transfer_penalty,
+            ..cost
+        }
+    }
+
+    /// Whether someone who has travelled `cost` so far may board another bus.
+    pub fn can_board(&self, cost: &TimeCost) -> bool {
+        match self.max_transfers {
+            Some(max) => !cost.has_boarded() || cost.transfers < max,
+            None => true,
+        }
+    }
+
+    /// How much of the buses taken so far decides what boarding another one
+    /// costs, for searches to tell apart ways to a stop by: nothing without a
+    /// transfer limit or penalty, whether any were taken with just a penalty,
+    /// and how many (up to one past the limit) with a limit.
+    pub fn boardings(&self, cost: &TimeCost) -> u32 {
+        if !cost.has_boarded() {
+            return 0;
+        }
+        match self.max_transfers {
+            Some(max) => (cost.transfers + 1).min(max + 1),
+            None if self.transfer_penalty != 0.0 => 1,
+            None => 0,
+        }
+    }
 }
 
 impl TimeCost {
@@ -15,6 +104,7 @@ impl TimeCost {
             bus_time: a,
             wait_time: a,
             transfers: 0,
+            generalized: 3.0 * a,
         }
     }
 
@@ -24,6 +114,7 @@ impl TimeCost {
             bus_time: 0.0,
             wait_time: 0.0,
             transfers: 0,
+            generalized: a,
         }
     }
 
@@ -32,7 +123,19 @@ impl TimeCost {
             walk_time: 0.0,
             bus_time: a,
             wait_time: 0.0,
+            transfers: 0,
+            generalized: a,
+        }
+    }
+
+    /// Boarding a bus after already having ridden one.
+    pub fn of_transfer() -> Self {
+        TimeCost {
+            walk_time: 0.0,
+            bus_time: 0.0,
+            wait_time: 0.0,
             transfers: 1,
+            generalized: 0.0,
         }
     }
 
@@ -42,6 +145,7 @@ impl TimeCost {
             bus_time: 0.0,
             wait_time: a,
             transfers: 0,
+            generalized: a,
         }
     }
 
@@ -51,9 +155,15 @@ impl TimeCost {
             bus_time,
             wait_time,
             transfers: _,
+            generalized: _,
         } = *self;
         walk_time + bus_time + wait_time
     }
+
+    /// Whether any bus has been taken, so the next one would be a transfer.
+    pub fn has_boarded(&self) -> bool {
+        self.bus_time > 0.0 || self.wait_time > 0.0
+    }
 }
 
 impl Eq for TimeCost {}
@@ -66,7 +176,7 @@ impl Ord for TimeCost {
 
 impl PartialOrd for TimeCost {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.total().partial_cmp(&other.total())
+        self.generalized.partial_cmp(&other.generalized)
     }
 }
 
@@ -78,13 +188,14 @@ impl ::std::ops::Add for TimeCost {
             bus_time: self.bus_time + other.bus_time,
             wait_time: self.wait_time + other.wait_time,
             transfers: self.transfers + other.transfers,
+            generalized: self.generalized + other.generalized,
         }
     }
 }
 
 impl ::num_traits::Zero for TimeCost {
     fn is_zero(&self) -> bool {
-        self.total() == 0.0
+        self.total() == 0.0 && self.generalized == 0.0
     }
 
     fn zero() -> Self {
@@ -93,6 +204,21 @@ impl ::num_traits::Zero for TimeCost {
             bus_time: 0.0,
             wait_time: 0.0,
             transfers: 0,
+            generalized: 0.0,
         }
     }
 }
+
+impl fmt::Display for TimeCost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} min ({:.1} walking, {:.1} on the bus, {:.1} waiting, {} transfers)",
+            self.total() / 60.0,
+            self.walk_time / 60.0,
+            self.bus_time / 60.0,
+            self.wait_time / 60.0,
+            self.transfers
+        )
+    }
+}
@@ -0,0 +1,177 @@
+use astar::{astar, SearchProblem};
+use model::Stops;
+use num_traits::Zero;
+use pathing::{Position, TransitSearchProblem, Unguided};
+use progress::Progress;
+use rayon::prelude::*;
+use std::fmt;
+use time::TimeCost;
+
+// Differences in generalized cost smaller than this many seconds are rounding,
+// not mistakes.
+const EPSILON: f32 = 1.0;
+// How many of each kind of problem get listed.
+const LISTED: usize = 10;
+
+/// A search where A* found a slower route than Dijkstra did, or none at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub origin: Position,
+    pub guided: Option<TimeCost>,
+    pub exact: TimeCost,
+}
+
+/// A place on the fastest route where the heuristic guessed more than the rest
+/// of the way takes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverEstimate {
+    pub origin: Position,
+    pub at: Position,
+    pub heuristic: TimeCost,
+    // Generalized cost of the rest of the route from `at`
+    pub remaining: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub origins: usize,
+    // Origins the destination can't be reached from at all
+    pub unreachable: usize,
+    pub mismatches: Vec<Mismatch>,
+    // Worst first
+    pub over_estimates: Vec<OverEstimate>,
+}
+
+impl Report {
+    /// Whether A* always found the fastest route and the heuristic never
+    /// over-estimated.
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.over_estimates.is_empty()
+    }
+}
+
+/// Up to `count` origins spread evenly over the stops, each just where a stop
+/// is, so searches from them walk to it before riding anything.
+pub fn sample_origins(stops: &Stops, count: usize) -> Vec<Position> {
+    let mut ids: Vec<_> = stops.keys().cloned().collect();
+    ids.sort_by_key(|id| id.0);
+    let step = (ids.len() / count.max(1)).max(1);
+    ids.iter()
+        .step_by(step)
+        .take(count)
+        .map(|id| Position::Custom(stops[id].stop_x, stops[id].stop_y))
+        .collect()
+}
+
+/// Searches from every one of `origins` both with the heuristic of `searcher`
+/// and without it, and checks the heuristic at each step of the fastest route
+/// against what the rest of the route really takes.
+pub fn check(searcher: &TransitSearchProblem, origins: &[Position]) -> Report {
+    let progress = Progress::new("Checking origins", origins.len());
+    let results: Vec<_> = origins
+        .par_iter()
+        .map(|&origin| {
+            let result = check_from(searcher, origin);
+            progress.add(1);
+            result
+        })
+        .collect();
+    progress.finish();
+
+    let mut report = Report {
+        origins: origins.len(),
+        unreachable: 0,
+        mismatches: vec![],
+        over_estimates: vec![],
+    };
+    for result in results {
+        match result {
+            Some((mismatch, over_estimates)) => {
+                report.mismatches.extend(mismatch);
+                report.over_estimates.extend(over_estimates);
+            }
+            None => report.unreachable += 1,
+        }
+    }
+    report.over_estimates.sort_by(|a, b| {
+        let by = |o: &OverEstimate| o.heuristic.generalized - o.remaining;
+        by(b).partial_cmp(&by(a)).unwrap()
+    });
+    report
+}
+
+fn check_from(
+    searcher: &TransitSearchProblem,
+    origin: Position,
+) -> Option<(Option<Mismatch>, Vec<OverEstimate>)> {
+    let (mut path, exact) = astar(&Unguided(searcher), origin)?;
+    let guided = astar(searcher, origin).map(|(_, cost)| cost);
+    let mismatch = match guided {
+        Some(guided) if guided.generalized <= exact.generalized + EPSILON => None,
+        _ => Some(Mismatch {
+            origin: origin,
+            guided: guided,
+            exact: exact,
+        }),
+    };
+
+    if path.front() != Some(&origin) {
+        path.push_front(origin);
+    }
+    // Retrace the route to know what it cost to get to each step.
+    let mut over_estimates = vec![];
+    let mut cost = TimeCost::zero();
+    let mut last: Option<Position> = None;
+    for at in path {
+        if let Some(last) = last {
+            let step = searcher
+                .neighbors(&last, &cost)
+                .filter(|&(next, _)| next == at)
+                .map(|(_, step)| step)
+                .min();
+            match step {
+                Some(step) => cost = cost + step,
+                None => break,
+            }
+        }
+        let heuristic = searcher.heuristic(&at);
+        let remaining = exact.generalized - cost.generalized;
+        if heuristic.generalized > remaining + EPSILON {
+            over_estimates.push(OverEstimate {
+                origin: origin,
+                at: at,
+                heuristic: heuristic,
+                remaining: remaining,
+            });
+        }
+        last = Some(at);
+    }
+    Some((mismatch, over_estimates))
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Checked {} origins, {} of which can't reach the destination",
+            self.origins, self.unreachable
+        )?;
+        writeln!(f, "A* missed the fastest route from {}", self.mismatches.len())?;
+        for m in self.mismatches.iter().take(LISTED) {
+            let guided = m.guided.map_or("nothing".to_string(), |g| g.to_string());
+            writeln!(f, "  from {:?}: found {}, but {} is possible", m.origin, guided, m.exact)?;
+        }
+        write!(f, "The heuristic over-estimated {} times", self.over_estimates.len())?;
+        for o in self.over_estimates.iter().take(LISTED) {
+            write!(
+                f,
+                "\n  at {:?} from {:?}: guessed {:.1} min, but {:.1} min is possible",
+                o.at,
+                o.origin,
+                o.heuristic.generalized / 60.0,
+                o.remaining / 60.0
+            )?;
+        }
+        Ok(())
+    }
+}